@@ -1,6 +1,6 @@
 use datagen::{parse_args, run_datagen};
 use monty::{
-    chess::{Chess, PolicyNetwork, ValueNetwork},
+    chess::{Chess, PolicyNetwork, QuantisedPolicyNetwork, ValueNetwork},
     GameRep, UciLike,
 };
 
@@ -15,9 +15,12 @@ static POLICY: PolicyNetwork = NETS.1;
 
 fn main() {
     let args = std::env::args();
-    let (threads, book, policy) = parse_args(args);
+    let (threads, book, use_policy) = parse_args(args);
 
-    monty::chess::Uci::bench(4, &POLICY, &VALUE);
+    // the policy is only stored unquantised, and quantised here
+    let policy = QuantisedPolicyNetwork::from_unquantised(&POLICY);
+
+    monty::chess::Uci::bench(4, &policy, &VALUE);
 
     if let Some(path) = &book {
         println!("Using book: {path}")
@@ -32,6 +35,6 @@ fn main() {
     params.set("cpuct", 1.08);
 
     run_datagen::<Chess, 112>(
-        params, 5_000, threads, policy, "Chess", &POLICY, &VALUE, book,
+        params, 5_000, threads, use_policy, "Chess", &policy, &VALUE, book,
     );
 }
@@ -0,0 +1,48 @@
+use datagen::pgn::{convert, OutputFormat};
+use monty::{chess::Chess, shatranj::Shatranj};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let (Some(input), Some(output)) = (args.next(), args.next()) else {
+        println!(
+            "usage: pgn <input.pgn> <output> [--format fen|epd|book|binpack] [--skip <plies>] [--variant chess|shatranj]"
+        );
+        return;
+    };
+
+    let mut format = OutputFormat::Fen;
+    let mut skip = 0;
+    let mut variant = String::from("chess");
+
+    let mut mode = 0;
+
+    for arg in args {
+        match arg.as_str() {
+            "--format" => mode = 1,
+            "--skip" => mode = 2,
+            "--variant" => mode = 3,
+            _ => match mode {
+                1 => {
+                    format = OutputFormat::from_name(&arg).expect("unknown format");
+                    mode = 0;
+                }
+                2 => {
+                    skip = arg.parse().expect("can't parse");
+                    mode = 0;
+                }
+                3 => {
+                    variant = arg;
+                    mode = 0;
+                }
+                _ => println!("unrecognised argument {arg}"),
+            },
+        }
+    }
+
+    match variant.as_str() {
+        "chess" => convert::<Chess>(&input, &output, format, skip),
+        "shatranj" => convert::<Shatranj>(&input, &output, format, skip),
+        _ => println!("unknown variant {variant}"),
+    }
+}
@@ -3,10 +3,44 @@ use monty::ataxx::{Ataxx, Board, Move};
 use crate::{BinpackType, DatagenSupport};
 
 impl DatagenSupport for Ataxx {
-    type CompressedBoard = Board;
+    const VARIANT: &'static str = "ataxx";
+
+    type CompressedBoard = CompressedAtaxxBoard;
     type Binpack = ();
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CompressedAtaxxBoard {
+    bbs: [u64; 3],
+    stm: bool,
+    halfm: u8,
+    fullm: u16,
+}
+
+impl From<Ataxx> for CompressedAtaxxBoard {
+    fn from(value: Ataxx) -> Self {
+        Self::from(*value.board())
+    }
+}
+
+impl From<Board> for CompressedAtaxxBoard {
+    fn from(board: Board) -> Self {
+        Self {
+            bbs: board.bbs(),
+            stm: board.stm() > 0,
+            halfm: board.halfm(),
+            fullm: board.fullm(),
+        }
+    }
+}
+
+impl From<CompressedAtaxxBoard> for Board {
+    fn from(value: CompressedAtaxxBoard) -> Self {
+        Board::from_raw(value.bbs, value.stm, value.halfm, value.fullm)
+    }
+}
+
 impl BinpackType<Ataxx> for <Ataxx as DatagenSupport>::Binpack {
     fn new(_: Ataxx) -> Self {}
 
@@ -3,6 +3,8 @@ use monty::chess::{Board, Castling, Chess, Move};
 use crate::{BinpackType, DatagenSupport};
 
 impl DatagenSupport for Chess {
+    const VARIANT: &'static str = "chess";
+
     type CompressedBoard = CompressedChessBoard;
     type Binpack = Binpack;
 }
@@ -73,7 +75,8 @@ impl From<CompressedChessBoard> for Board {
         bbs[6] = pbq & prq & rqk;
         bbs[7] = nbk & rqk;
 
-        Board::from_raw(bbs, value.stm, value.enp_sq, value.rights, value.halfm)
+        // the packed format has no move number
+        Board::from_raw(bbs, value.stm, value.enp_sq, value.rights, value.halfm, 1)
     }
 }
 
@@ -3,6 +3,8 @@ use monty::shatranj::{Board, Move, Shatranj};
 use crate::{BinpackType, DatagenSupport};
 
 impl DatagenSupport for Shatranj {
+    const VARIANT: &'static str = "shatranj";
+
     type CompressedBoard = CompressedShatranjBoard;
     type Binpack = Binpack;
 }
@@ -63,7 +65,8 @@ impl From<CompressedShatranjBoard> for Board {
         bbs[6] = pbq & prq & rqk;
         bbs[7] = nbk & rqk;
 
-        Board::from_raw(bbs, value.stm, value.halfm)
+        // the packed format has no move number
+        Board::from_raw(bbs, value.stm, value.halfm, 1)
     }
 }
 
@@ -1,4 +1,5 @@
 pub mod impls;
+pub mod pgn;
 mod rng;
 mod thread;
 
@@ -22,7 +23,7 @@ pub type ShatranjPolicyData = PolicyData<Shatranj, 112>;
 #[repr(C)]
 pub struct PolicyData<T: DatagenSupport, const MAX: usize> {
     pub pos: T::CompressedBoard,
-    pub moves: [(u16, u16); MAX],
+    pub moves: [(u16, u32); MAX],
     pub num: usize,
     pub score: f32,
     pub result: f32,
@@ -41,8 +42,8 @@ impl<T: DatagenSupport, const MAX: usize> PolicyData<T, MAX> {
         }
     }
 
-    pub fn push(&mut self, mov: T::Move, visits: i32) {
-        self.moves[self.num] = (mov.into(), visits as u16);
+    pub fn push(&mut self, mov: T::Move, visits: u32) {
+        self.moves[self.num] = (mov.into(), visits);
         self.num += 1;
     }
 
@@ -52,6 +53,9 @@ impl<T: DatagenSupport, const MAX: usize> PolicyData<T, MAX> {
 }
 
 pub trait DatagenSupport: GameRep {
+    /// Name of the game in the `Variant` tag of a PGN.
+    const VARIANT: &'static str;
+
     type CompressedBoard: Copy + From<Self>;
     type Binpack: BinpackType<Self>;
 }
@@ -0,0 +1,570 @@
+use crate::{BinpackType, DatagenSupport};
+
+use monty::{FenError, GameRep};
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    iter::Peekable,
+};
+
+pub struct PgnGame<T: GameRep> {
+    pub tags: Vec<(String, String)>,
+    pub startpos: T,
+    pub moves: Vec<PgnMove<T>>,
+    /// Result from white's point of view, if the game was finished.
+    pub result: Option<f32>,
+}
+
+pub struct PgnMove<T: GameRep> {
+    pub mov: T::Move,
+    /// The first comment following the move, if any.
+    pub comment: Option<String>,
+}
+
+impl<T: GameRep> PgnGame<T> {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every position in the game, starting with `startpos`,
+    /// along with the move that was played from it.
+    pub fn positions(&self) -> Vec<(T, Option<&PgnMove<T>>)> {
+        let mut pos = self.startpos.clone();
+        let mut positions = Vec::new();
+
+        for mov in &self.moves {
+            positions.push((pos.clone(), Some(mov)));
+            pos.make_move(mov.mov);
+        }
+
+        positions.push((pos, None));
+        positions
+    }
+}
+
+#[derive(Debug)]
+pub enum PgnError {
+    Io(std::io::Error),
+    Fen(FenError),
+    UnsupportedVariant(String),
+    IllegalMove(String),
+}
+
+impl std::fmt::Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgnError::Io(err) => write!(f, "{err}"),
+            PgnError::Fen(err) => write!(f, "invalid FEN tag: {err}"),
+            PgnError::UnsupportedVariant(variant) => write!(f, "unsupported variant '{variant}'"),
+            PgnError::IllegalMove(mov) => write!(f, "illegal move '{mov}'"),
+        }
+    }
+}
+
+/// Reads games one at a time from a PGN file. A game that can't be
+/// parsed is returned as an error, and reading continues with the next.
+pub struct PgnReader<R: BufRead, T: DatagenSupport> {
+    lines: Peekable<Lines<R>>,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<R: BufRead, T: DatagenSupport> PgnReader<R, T> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines().peekable(),
+            marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<R: BufRead, T: DatagenSupport> Iterator for PgnReader<R, T> {
+    type Item = Result<PgnGame<T>, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tags = Vec::new();
+        let mut movetext = String::new();
+        let mut in_comment = false;
+
+        while let Some(line) = self.lines.peek() {
+            let Ok(line) = line else {
+                let err = self.lines.next()?.unwrap_err();
+                return Some(Err(PgnError::Io(err)));
+            };
+
+            let line = line.trim();
+
+            if !in_comment && line.starts_with('[') {
+                // the tags of the next game
+                if !movetext.trim().is_empty() {
+                    break;
+                }
+
+                if let Some(tag) = parse_tag(line) {
+                    tags.push(tag);
+                }
+            } else {
+                for ch in line.chars() {
+                    match ch {
+                        '{' => in_comment = true,
+                        '}' => in_comment = false,
+                        _ => {}
+                    }
+                }
+
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+
+            self.lines.next();
+        }
+
+        if tags.is_empty() && movetext.trim().is_empty() {
+            return None;
+        }
+
+        Some(parse_game(tags, &movetext))
+    }
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.trim_end().strip_suffix(']')?;
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+    Some((name.to_string(), value.replace("\\\"", "\"")))
+}
+
+fn parse_game<T: DatagenSupport>(
+    tags: Vec<(String, String)>,
+    movetext: &str,
+) -> Result<PgnGame<T>, PgnError> {
+    let tag = |name: &str| {
+        tags.iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let is_chess = T::VARIANT == "chess";
+
+    let chess960 = match tag("Variant").map(str::to_ascii_lowercase).as_deref() {
+        None | Some("from position") => false,
+        Some("standard") if is_chess => false,
+        Some("chess960" | "fischerandom" | "fischer random" | "chess 960") if is_chess => true,
+        Some(variant) if variant == T::VARIANT => false,
+        Some(_) => return Err(PgnError::UnsupportedVariant(tag("Variant").unwrap().into())),
+    };
+
+    let startpos = match tag("FEN") {
+        Some(fen) if chess960 => T::try_from_fen(&shredder_castling(fen)),
+        Some(fen) => T::try_from_fen(fen),
+        None => Ok(T::from_fen(T::STARTPOS)),
+    };
+
+    let mut pos = startpos.map_err(PgnError::Fen)?;
+
+    let mut game = PgnGame {
+        startpos: pos.clone(),
+        moves: Vec::new(),
+        result: tag("Result").and_then(parse_result),
+        tags: Vec::new(),
+    };
+
+    let mut chars = movetext.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' => {
+                let comment = chars.by_ref().take_while(|&ch| ch != '}').collect();
+                add_comment(&mut game, comment);
+            }
+            ';' => {
+                let comment = chars.by_ref().take_while(|&ch| ch != '\n').collect();
+                add_comment(&mut game, comment);
+            }
+            '(' => skip_variation(&mut chars),
+            '$' => while chars.next_if(char::is_ascii_digit).is_some() {},
+            _ if ch.is_whitespace() => {}
+            _ => {
+                let mut token = String::from(ch);
+
+                while let Some(ch) =
+                    chars.next_if(|ch| !ch.is_whitespace() && !"{};()$".contains(*ch))
+                {
+                    token.push(ch);
+                }
+
+                // move numbers, such as `12.` or `12...e5`
+                let token = token.rsplit('.').next().unwrap_or_default();
+
+                if let Some(result) = parse_result(token) {
+                    game.result = Some(result);
+                    break;
+                }
+
+                if token.is_empty() || token == "*" {
+                    continue;
+                }
+
+                let mov = pos
+                    .parse_san(token)
+                    .ok_or_else(|| PgnError::IllegalMove(token.to_string()))?;
+
+                pos.make_move(mov);
+                game.moves.push(PgnMove { mov, comment: None });
+            }
+        }
+    }
+
+    game.tags = tags;
+
+    Ok(game)
+}
+
+fn parse_result(result: &str) -> Option<f32> {
+    match result {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        _ => None,
+    }
+}
+
+fn add_comment<T: GameRep>(game: &mut PgnGame<T>, comment: String) {
+    if let Some(mov) = game.moves.last_mut() {
+        mov.comment.get_or_insert(comment);
+    }
+}
+
+fn skip_variation(chars: &mut Peekable<std::str::Chars>) {
+    let mut depth = 1;
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '{' => while chars.next().is_some_and(|ch| ch != '}') {},
+            _ => {}
+        }
+
+        if depth == 0 {
+            break;
+        }
+    }
+}
+
+/// Chess960 FENs often give castling rights as `KQkq`, meaning the outermost
+/// rook on that side of the king, which is replaced by the rook's file.
+fn shredder_castling(fen: &str) -> String {
+    let mut fields = fen.split_whitespace().map(String::from).collect::<Vec<_>>();
+
+    if fields.len() < 3 || fields[0].split('/').count() != 8 {
+        return fen.to_string();
+    }
+
+    let ranks = fields[0].split('/').collect::<Vec<_>>();
+
+    let expand = |rank: &str| {
+        rank.chars()
+            .flat_map(|ch| match ch.to_digit(10) {
+                Some(empty) => vec!['.'; empty as usize],
+                None => vec![ch],
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let back_ranks = [expand(ranks[7]), expand(ranks[0])];
+
+    let rights = fields[2]
+        .chars()
+        .map(|ch| {
+            let side = usize::from(ch.is_ascii_lowercase());
+            let rank = &back_ranks[side];
+            let king = rank.iter().position(|&pc| pc == ['K', 'k'][side]);
+            let is_rook = |pc: &char| *pc == ['R', 'r'][side];
+
+            let file = match (ch.to_ascii_uppercase(), king) {
+                ('K', Some(king)) => rank.iter().rposition(is_rook).filter(|&file| file > king),
+                ('Q', Some(king)) => rank.iter().position(is_rook).filter(|&file| file < king),
+                _ => None,
+            };
+
+            file.map_or(ch, |file| char::from([b'A', b'a'][side] + file as u8))
+        })
+        .collect();
+
+    fields[2] = rights;
+    fields.join(" ")
+}
+
+/// Score of a move for the side that played it, from an engine evaluation
+/// in its comment, either `[%eval ...]` from white's point of view or
+/// `+1.23/20 ...` from that of the engine that played it, as cutechess writes.
+fn comment_score<T: GameRep>(comment: &str, white_moved: bool) -> Option<f32> {
+    let parse = |eval: &str| {
+        if let Some(mate) = eval.strip_prefix('#').or_else(|| eval.strip_prefix("+M")) {
+            Some(if mate.starts_with('-') { 0.0 } else { 1.0 })
+        } else if eval.starts_with("-M") {
+            Some(0.0)
+        } else {
+            eval.parse::<f32>()
+                .ok()
+                .map(|pawns| T::cp_to_wdl(100.0 * pawns))
+        }
+    };
+
+    if let Some((_, eval)) = comment.split_once("[%eval ") {
+        let score = parse(eval.split(']').next()?.trim())?;
+        Some(if white_moved { score } else { 1.0 - score })
+    } else {
+        parse(comment.split_whitespace().next()?.split('/').next()?)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Every position as a FEN, one per line.
+    Fen,
+    /// Every position as an EPD with the game result in a `c9` opcode.
+    Epd,
+    /// One position per game, for use as a datagen book.
+    Book,
+    /// One binpack record per game, scored from engine evaluations
+    /// in the move comments where present.
+    Binpack,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fen" => Some(Self::Fen),
+            "epd" => Some(Self::Epd),
+            "book" => Some(Self::Book),
+            "binpack" => Some(Self::Binpack),
+            _ => None,
+        }
+    }
+}
+
+/// Converts every game in `input`, skipping the first `skip` plies of each. Games
+/// without a result are left out of binpacks and EPDs, which need one.
+pub fn convert<T: DatagenSupport>(input: &str, output: &str, format: OutputFormat, skip: usize) {
+    let reader = BufReader::new(File::open(input).expect("Provide a correct path!"));
+    let mut writer = BufWriter::new(File::create(output).expect("Provide a correct path!"));
+
+    let mut games = 0;
+    let mut positions = 0;
+    let mut errors = 0;
+
+    for (i, game) in PgnReader::<_, T>::new(reader).enumerate() {
+        let game = match game {
+            Ok(game) => game,
+            Err(err) => {
+                println!("game {}: {err}", i + 1);
+                errors += 1;
+                continue;
+            }
+        };
+
+        if game.result.is_none() && matches!(format, OutputFormat::Epd | OutputFormat::Binpack) {
+            continue;
+        }
+
+        let result = game.result.unwrap_or(0.5);
+        let all = game.positions();
+        let kept = all.get(skip..).unwrap_or_default();
+
+        match format {
+            OutputFormat::Fen => {
+                for (pos, _) in kept {
+                    writeln!(writer, "{}", pos.as_fen()).unwrap();
+                }
+
+                positions += kept.len();
+            }
+            OutputFormat::Epd => {
+                let result = ["0-1", "1/2-1/2", "1-0"][(2.0 * result) as usize];
+
+                for (pos, _) in kept {
+                    let fen = pos.as_fen();
+                    let epd = fen.split_whitespace().take(4).collect::<Vec<_>>().join(" ");
+                    writeln!(writer, "{epd} c9 \"{result}\";").unwrap();
+                }
+
+                positions += kept.len();
+            }
+            OutputFormat::Book => {
+                if let Some((pos, _)) = kept.first() {
+                    writeln!(writer, "{}", pos.as_fen()).unwrap();
+                    positions += 1;
+                }
+            }
+            OutputFormat::Binpack => {
+                let Some((startpos, _)) = kept.first() else {
+                    continue;
+                };
+
+                let mut binpack = T::Binpack::new(startpos.clone());
+
+                for (pos, mov) in kept {
+                    let Some(mov) = mov else {
+                        break;
+                    };
+
+                    let white = pos.stm() == 0;
+                    let score = mov
+                        .comment
+                        .as_deref()
+                        .and_then(|comment| comment_score::<T>(comment, white))
+                        .unwrap_or(0.5);
+
+                    binpack.push(pos.stm(), mov.mov, score);
+                    positions += 1;
+                }
+
+                binpack.set_result(result);
+                binpack.serialise_into(&mut writer).unwrap();
+            }
+        }
+
+        games += 1;
+    }
+
+    println!("converted {games} games ({positions} positions), {errors} could not be read");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monty::{chess::Chess, shatranj::Shatranj};
+
+    fn read<T: DatagenSupport>(pgn: &str) -> Vec<Result<PgnGame<T>, PgnError>> {
+        PgnReader::new(pgn.as_bytes()).collect()
+    }
+
+    fn moves<T: DatagenSupport>(game: &PgnGame<T>) -> Vec<String> {
+        game.positions()
+            .iter()
+            .filter_map(|(pos, mov)| mov.map(|mov| pos.conv_mov_to_str(mov.mov)))
+            .collect()
+    }
+
+    fn last_fen<T: DatagenSupport>(game: &PgnGame<T>) -> String {
+        game.positions().last().unwrap().0.as_fen()
+    }
+
+    #[test]
+    fn tags() {
+        let pgn = "[Event \"a \\\"quoted\\\" name\"]\n[White \"monty\"]\n\n1. e4 *\n";
+        let game = read::<Chess>(pgn).remove(0).unwrap();
+
+        assert_eq!(game.tag("Event"), Some("a \"quoted\" name"));
+        assert_eq!(game.tag("White"), Some("monty"));
+        assert_eq!(game.tag("Black"), None);
+    }
+
+    #[test]
+    fn comments_and_nags() {
+        let pgn = "1. e4 {best by test} e5 $1 2. Nf3 ; to the end of the line\nNc6! $14 {first} {second} *";
+        let game = read::<Chess>(pgn).remove(0).unwrap();
+
+        assert_eq!(moves(&game), ["e2e4", "e7e5", "g1f3", "b8c6"]);
+
+        let comments = game
+            .moves
+            .iter()
+            .map(|mov| mov.comment.as_deref())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            comments,
+            [
+                Some("best by test"),
+                None,
+                Some(" to the end of the line"),
+                Some("first"),
+            ]
+        );
+    }
+
+    #[test]
+    fn variations() {
+        let pgn = "1. e4 (1. d4 d5 (1... Nf6 {indian}) 2. c4) 1... e5 (1... c5) 2. Nf3 *";
+        let game = read::<Chess>(pgn).remove(0).unwrap();
+
+        assert_eq!(moves(&game), ["e2e4", "e7e5", "g1f3"]);
+    }
+
+    #[test]
+    fn results() {
+        let pgn = "[Result \"1-0\"]\n\n1. e4 1-0\n\n\
+                   [Result \"0-1\"]\n\n1. e4 0-1\n\n\
+                   [Result \"1/2-1/2\"]\n\n1. e4 1/2-1/2\n\n\
+                   [Result \"*\"]\n\n1. e4 *\n\n\
+                   [Result \"1-0\"]\n\n1. e4\n";
+
+        let results = read::<Chess>(pgn)
+            .into_iter()
+            .map(|game| game.unwrap().result)
+            .collect::<Vec<_>>();
+
+        assert_eq!(results, [Some(1.0), Some(0.0), Some(0.5), None, Some(1.0)]);
+    }
+
+    #[test]
+    fn errors_are_skipped() {
+        let pgn = "1. e5 *\n\n[Variant \"crazyhouse\"]\n\n1. e4 *\n\n[White \"next\"]\n\n1. d4 *\n";
+        let games = read::<Chess>(pgn);
+
+        assert!(matches!(games[0], Err(PgnError::IllegalMove(_))));
+        assert!(matches!(games[1], Err(PgnError::UnsupportedVariant(_))));
+        assert_eq!(moves(games[2].as_ref().unwrap()), ["d2d4"]);
+    }
+
+    #[test]
+    fn fullmove_number() {
+        let game = read::<Chess>("1. e4 e5 2. Nf3 *").remove(0).unwrap();
+
+        assert_eq!(
+            last_fen(&game),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+    }
+
+    #[test]
+    fn frc_castling() {
+        let game = |fen: &str, castle: &str| {
+            let pgn = format!("[Variant \"Chess960\"]\n[FEN \"{fen}\"]\n\n1. {castle} *\n");
+            last_fen(&read::<Chess>(&pgn).remove(0).unwrap())
+        };
+
+        // `KQ` is the outermost rook on each side
+        let fen = "4k3/8/8/8/8/8/8/1R1K2R1 w KQ - 0 12";
+        assert_eq!(game(fen, "O-O"), "4k3/8/8/8/8/8/8/1R3RK1 b - - 1 12");
+        assert_eq!(game(fen, "0-0-0"), "4k3/8/8/8/8/8/8/2KR2R1 b - - 1 12");
+
+        let shredder = "4k3/8/8/8/8/8/8/1R1K2R1 w GB - 0 12";
+        assert_eq!(game(shredder, "O-O"), "4k3/8/8/8/8/8/8/1R3RK1 b - - 1 12");
+    }
+
+    #[test]
+    fn shatranj() {
+        let pgn = "[Variant \"Shatranj\"]\n\n1. d3 e6 2. Fd2 Ad6 3. Qe1 *\n";
+        let game = read::<Shatranj>(pgn).remove(0).unwrap();
+
+        assert_eq!(moves(&game), ["d2d3", "e7e6", "e1d2", "f8d6", "d2e1"]);
+        assert_eq!(
+            last_fen(&game),
+            "rnbkq1nr/pppp1ppp/3bp3/8/8/3P4/PPP1PPPP/RNBKQBNR b - - 3 3"
+        );
+
+        for variant in ["Standard", "Chess960"] {
+            let pgn = format!("[Variant \"{variant}\"]\n\n1. d3 *\n");
+            let game = read::<Shatranj>(&pgn).remove(0);
+            assert!(matches!(game, Err(PgnError::UnsupportedVariant(_))));
+        }
+    }
+}
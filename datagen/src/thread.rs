@@ -122,6 +122,9 @@ impl<'a, T: DatagenSupport> DatagenThread<'a, T> {
             max_depth: 12,
             max_nodes: node_limit,
             max_time: None,
+            max_mate: None,
+            infinite: false,
+            soft_nodes: None,
         };
 
         let mut records = Vec::new();
@@ -149,7 +152,7 @@ impl<'a, T: DatagenSupport> DatagenThread<'a, T> {
             if root_count <= MAX_MOVES {
                 let mut policy_pos = PolicyData::<T, MAX_MOVES>::new(position.clone(), bm, score);
 
-                for action in tree[tree.root_node()].actions() {
+                for action in tree.actions(tree.root_node()) {
                     policy_pos.push(action.mov().into(), action.visits());
                 }
 
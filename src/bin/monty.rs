@@ -1,5 +1,5 @@
 use monty::{
-    chess::{PolicyNetwork, Uci, ValueNetwork},
+    chess::{PolicyNetwork, QuantisedPolicyNetwork, Uci, ValueNetwork},
     UciLike,
 };
 
@@ -15,10 +15,23 @@ fn main() {
     let mut args = std::env::args();
     let arg1 = args.nth(1);
 
+    // the policy is only stored unquantised, and quantised here
+    let policy = QuantisedPolicyNetwork::from_unquantised(&POLICY);
+
     if let Some("bench") = arg1.as_deref() {
-        monty::chess::Uci::bench(5, &POLICY, &VALUE);
+        monty::chess::Uci::bench(5, &policy, &VALUE);
+        return;
+    }
+
+    if let Some("epd") = arg1.as_deref() {
+        Uci::epd(&args.collect::<Vec<_>>(), &policy, &VALUE);
+        return;
+    }
+
+    if let Some("perft") = arg1.as_deref() {
+        Uci::perft(&args.collect::<Vec<_>>());
         return;
     }
 
-    Uci::run(&POLICY, &VALUE);
+    Uci::run(&policy, &VALUE);
 }
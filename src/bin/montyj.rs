@@ -20,5 +20,15 @@ fn main() {
         return;
     }
 
+    if let Some("epd") = arg1.as_deref() {
+        Uci::epd(&args.collect::<Vec<_>>(), &POLICY, &VALUE);
+        return;
+    }
+
+    if let Some("perft") = arg1.as_deref() {
+        Uci::perft(&args.collect::<Vec<_>>());
+        return;
+    }
+
     Uci::run(&POLICY, &VALUE);
 }
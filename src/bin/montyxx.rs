@@ -20,5 +20,10 @@ fn main() {
         return;
     }
 
+    if let Some("perft") = arg1.as_deref() {
+        Uai::perft(&args.collect::<Vec<_>>());
+        return;
+    }
+
     Uai::run(&POLICY, &VALUE);
 }
@@ -1,10 +1,19 @@
+mod epd;
+
 use crate::{
-    games::GameRep,
-    mcts::{Limits, Searcher},
+    games::{self, GameRep, PerftResult},
+    mcts::{Limits, MoveSelection, Searcher},
+    tree::{DEFAULT_HASH_FRACTION, DEFAULT_REUSE_DEPTH},
     MctsParams, Tree,
 };
 
-use std::time::Instant;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    time::Instant,
+};
 
 pub trait UciLike: Sized {
     type Game: GameRep;
@@ -19,24 +28,28 @@ pub trait UciLike: Sized {
         let mut prev = None;
         let mut pos = Self::Game::default();
         let mut params = Self::Game::default_mcts_params();
-        let mut tree = Tree::new_mb(64);
+        let mut tree_options = TreeOptions::default();
+        let mut tree = tree_options.new_tree();
         let mut report_moves = false;
+        let mut move_selection = MoveSelection::default();
 
-        loop {
-            let mut input = String::new();
-            let bytes_read = std::io::stdin().read_line(&mut input).unwrap();
-
-            if bytes_read == 0 {
-                break;
-            }
+        let signals = Arc::new(SearchSignals::default());
+        let receiver = spawn_input_thread(signals.clone());
 
+        while let Ok(input) = receiver.recv() {
             let commands = input.split_whitespace().collect::<Vec<_>>();
 
             let cmd = *commands.first().unwrap_or(&"oops");
             match cmd {
-                "isready" => println!("readyok"),
-                "setoption" => setoption(&commands, &mut params, &mut report_moves, &mut tree),
-                "position" => position(commands, &mut pos, &mut prev, &mut tree),
+                "setoption" => setoption(
+                    &commands,
+                    &mut params,
+                    &mut report_moves,
+                    &mut tree,
+                    &mut tree_options,
+                    &mut move_selection,
+                ),
+                "position" => position(commands, &mut pos, &mut prev, &mut tree, &params, policy),
                 "go" => {
                     let res = go(
                         &commands,
@@ -45,6 +58,8 @@ pub trait UciLike: Sized {
                         &pos,
                         &params,
                         report_moves,
+                        move_selection,
+                        &signals,
                         policy,
                         value,
                     );
@@ -53,19 +68,18 @@ pub trait UciLike: Sized {
                     prev = Some(res.1);
                 }
                 "perft" => run_perft::<Self::Game>(&commands, &pos),
+                "isready" => println!("readyok"),
                 "quit" => std::process::exit(0),
                 "eval" => {
                     println!("cp: {}%", pos.get_value(value));
                     println!("wdl: {:.2}%", 100.0 * pos.get_value_wdl(value));
                 }
                 "policy" => {
-                    let f = pos.get_policy_feats();
                     let mut max = f32::NEG_INFINITY;
                     let mut moves = Vec::new();
 
-                    pos.map_legal_moves(|mov| {
+                    pos.map_policies(policy, |mov, p| {
                         let s = pos.conv_mov_to_str(mov);
-                        let p = pos.get_policy(mov, &f, policy);
 
                         if p > max {
                             max = p;
@@ -94,7 +108,7 @@ pub trait UciLike: Sized {
                     println!("filled {u}/{c} ({pct:.2}%)");
 
                     let depth = commands.get(1).unwrap_or(&"5").parse().unwrap_or(5);
-                    tree.display::<Self::Game>(tree.root_node(), depth);
+                    tree.display(tree.root_node(), &pos, depth);
                 }
                 "d" => pos.display(policy),
                 _ => {
@@ -123,6 +137,9 @@ pub trait UciLike: Sized {
             max_time: None,
             max_depth: depth,
             max_nodes: 1_000_000,
+            max_mate: None,
+            infinite: false,
+            soft_nodes: None,
         };
 
         let mut tree = Tree::new_mb(32);
@@ -142,6 +159,145 @@ pub trait UciLike: Sized {
             total_nodes as f32 / time
         );
     }
+
+    /// Runs perft, given the arguments
+    /// `<depth> [divide] [hash] [nobulk] [fen <fen>]`.
+    fn perft(args: &[String]) {
+        let fen_idx = args.iter().position(|arg| arg == "fen");
+
+        let pos = if let Some(idx) = fen_idx {
+            let fen = args[idx + 1..].join(" ");
+
+            match Self::Game::try_from_fen(&fen) {
+                Ok(pos) => pos,
+                Err(err) => {
+                    println!("invalid fen '{fen}': {err}");
+                    return;
+                }
+            }
+        } else {
+            Self::Game::from_fen(Self::Game::STARTPOS)
+        };
+
+        let args = &args[..fen_idx.unwrap_or(args.len())];
+        let commands = std::iter::once("perft")
+            .chain(args.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+
+        run_perft(&commands, &pos);
+    }
+
+    /// Runs an EPD test suite, given the arguments
+    /// `<file> [nodes <n>] [softnodes <n>] [movetime <ms>] [csv <file>]`.
+    fn epd(
+        args: &[String],
+        policy: &<Self::Game as GameRep>::Policy,
+        value: &<Self::Game as GameRep>::Value,
+    ) {
+        let Some(path) = args.first() else {
+            println!("usage: epd <file> [nodes <n>] [softnodes <n>] [movetime <ms>] [csv <file>]");
+            return;
+        };
+
+        let mut limits = Limits {
+            max_time: None,
+            max_depth: 256,
+            max_nodes: 100_000,
+            max_mate: None,
+            infinite: false,
+            soft_nodes: None,
+        };
+
+        let mut csv = None;
+
+        for pair in args[1..].chunks(2) {
+            match (pair[0].as_str(), pair.get(1)) {
+                ("nodes", Some(n)) => limits.max_nodes = n.parse().unwrap_or(limits.max_nodes),
+                ("softnodes", Some(n)) => limits.soft_nodes = n.parse().ok(),
+                ("movetime", Some(ms)) => limits.max_time = ms.parse().ok(),
+                ("csv", Some(out)) => csv = Some(out.as_str()),
+                _ => println!("ignoring unknown epd option '{}'", pair[0]),
+            }
+        }
+
+        epd::run::<Self::Game>(path, limits, csv, policy, value);
+    }
+}
+
+/// Shared between the input thread and the main loop, counting
+/// `go` commands so that a `stop` only applies to searches that
+/// were asked for before it.
+#[derive(Default)]
+struct SearchSignals {
+    abort: AtomicBool,
+    // `go` commands read, read before the last `stop`, and finished
+    read: AtomicUsize,
+    stopped: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+impl SearchSignals {
+    fn is_searching(&self) -> bool {
+        self.read.load(Ordering::SeqCst) > self.finished.load(Ordering::SeqCst)
+    }
+
+    fn stop(&self) {
+        self.stopped
+            .store(self.read.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.abort.store(true, Ordering::SeqCst);
+    }
+
+    /// Called on the main thread just before a search starts, so a
+    /// `stop` read before the `go` for this search doesn't end it.
+    fn start_search(&self) {
+        let search = self.finished.load(Ordering::SeqCst) + 1;
+        self.abort.store(false, Ordering::SeqCst);
+
+        if self.stopped.load(Ordering::SeqCst) >= search {
+            self.abort.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn finish_search(&self) {
+        self.finished.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Reads commands from stdin on a separate thread, so that `stop` is
+/// handled immediately, even during search. `isready` is answered
+/// straight away while a search is running, and otherwise queued with
+/// everything else so it is only answered after earlier commands.
+fn spawn_input_thread(signals: Arc<SearchSignals>) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || loop {
+        let mut input = String::new();
+        let bytes_read = std::io::stdin().read_line(&mut input).unwrap();
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        match input.split_whitespace().next() {
+            Some("stop") => signals.stop(),
+            Some("isready") if signals.is_searching() => println!("readyok"),
+            cmd => {
+                match cmd {
+                    Some("go") => {
+                        signals.read.fetch_add(1, Ordering::SeqCst);
+                    }
+                    Some("quit") => signals.stop(),
+                    _ => {}
+                }
+
+                if sender.send(input).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    receiver
 }
 
 fn preamble<T: UciLike>() {
@@ -149,12 +305,64 @@ fn preamble<T: UciLike>() {
     println!("id author Jamie Whiting");
     println!("option name Hash type spin default 64 min 1 max 8192");
     println!("option name report_moves type button");
+    println!(
+        "option name HashFraction type spin default {:.0} min 1 max 50",
+        DEFAULT_HASH_FRACTION * 100.0
+    );
+    println!("option name GraphSearch type check default false");
+
+    print!("option name MoveSelection type combo default Q");
+    for (name, _) in MoveSelection::OPTIONS {
+        print!(" var {name}");
+    }
+    println!();
+
+    println!("option name TreeReuseDepth type spin default {DEFAULT_REUSE_DEPTH} min 0 max 16");
     T::options();
     MctsParams::info(T::Game::default_mcts_params());
     println!("{}", T::OK);
 }
 
-fn setoption(commands: &[&str], params: &mut MctsParams, report_moves: &mut bool, tree: &mut Tree) {
+/// Options that require the tree to be reallocated when changed.
+struct TreeOptions {
+    hash_mb: usize,
+    hash_fraction: f32,
+    graph_search: bool,
+    reuse_depth: u8,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        Self {
+            hash_mb: 64,
+            hash_fraction: DEFAULT_HASH_FRACTION,
+            graph_search: false,
+            reuse_depth: DEFAULT_REUSE_DEPTH,
+        }
+    }
+}
+
+impl TreeOptions {
+    fn new_tree(&self) -> Tree {
+        let mut tree = Tree::with_hash_fraction(self.hash_mb, self.hash_fraction);
+        tree.set_reuse_depth(self.reuse_depth);
+
+        if self.graph_search {
+            tree.set_graph_search(true);
+        }
+
+        tree
+    }
+}
+
+fn setoption(
+    commands: &[&str],
+    params: &mut MctsParams,
+    report_moves: &mut bool,
+    tree: &mut Tree,
+    tree_options: &mut TreeOptions,
+    move_selection: &mut MoveSelection,
+) {
     if let ["setoption", "name", "report_moves"] = commands {
         *report_moves = !*report_moves;
         return;
@@ -165,19 +373,50 @@ fn setoption(commands: &[&str], params: &mut MctsParams, report_moves: &mut bool
             return;
         }
 
+        if *x == "MoveSelection" {
+            if let Some(selection) = MoveSelection::from_name(y) {
+                *move_selection = selection;
+            }
+
+            return;
+        }
+
+        if *x == "GraphSearch" {
+            tree_options.graph_search = *y == "true";
+            tree.set_graph_search(tree_options.graph_search);
+            return;
+        }
+
         (*x, y.parse::<i32>().unwrap_or(0))
     } else {
         return;
     };
 
-    if name == "Hash" {
-        *tree = Tree::new_mb(val as usize);
-    } else {
-        params.set(name, val as f32 / 100.0);
+    match name {
+        "Hash" => {
+            tree_options.hash_mb = val.max(1) as usize;
+            *tree = tree_options.new_tree();
+        }
+        "HashFraction" => {
+            tree_options.hash_fraction = val.clamp(1, 50) as f32 / 100.0;
+            *tree = tree_options.new_tree();
+        }
+        "TreeReuseDepth" => {
+            tree_options.reuse_depth = val.clamp(0, 16) as u8;
+            tree.set_reuse_depth(tree_options.reuse_depth);
+        }
+        _ => params.set(name, val as f32 / 100.0),
     }
 }
 
-fn position<T: GameRep>(commands: Vec<&str>, pos: &mut T, prev: &mut Option<T>, tree: &mut Tree) {
+fn position<T: GameRep>(
+    commands: Vec<&str>,
+    pos: &mut T,
+    prev: &mut Option<T>,
+    tree: &mut Tree,
+    params: &MctsParams,
+    policy: &T::Policy,
+) {
     let mut fen = String::new();
     let mut move_list = Vec::new();
     let mut moves = false;
@@ -197,21 +436,24 @@ fn position<T: GameRep>(commands: Vec<&str>, pos: &mut T, prev: &mut Option<T>,
         }
     }
 
-    *pos = T::from_fen(&fen);
+    *pos = match T::try_from_fen(&fen) {
+        Ok(new_pos) => new_pos,
+        Err(err) => {
+            println!("info string invalid fen '{}': {err}", fen.trim());
+            return;
+        }
+    };
 
     for &m in move_list.iter() {
-        let mut this_mov = T::Move::default();
-
-        pos.map_legal_moves(|mov| {
-            if m == pos.conv_mov_to_str(mov) {
-                this_mov = mov;
-            }
-        });
+        let Some(mov) = pos.parse_move(m) else {
+            println!("info string illegal move '{m}', ignoring it and all following moves");
+            break;
+        };
 
-        pos.make_move(this_mov);
+        pos.make_move(mov);
     }
 
-    tree.try_use_subtree(pos, prev);
+    tree.try_use_subtree(pos, prev, params, policy);
     *prev = Some(pos.clone());
 }
 
@@ -223,22 +465,29 @@ fn go<T: GameRep>(
     pos: &T,
     params: &MctsParams,
     report_moves: bool,
+    move_selection: MoveSelection,
+    signals: &SearchSignals,
     policy: &T::Policy,
     value: &T::Value,
 ) -> (Tree, T) {
     let mut max_nodes = 10_000_000;
     let mut max_time = None;
     let mut max_depth = 256;
+    let mut max_mate = None;
+    let mut infinite = false;
+    let mut search_moves = Vec::new();
 
     let mut times = [None; 2];
     let mut incs = [None; 2];
-    let mut movestogo = 30;
+    let mut movestogo = None;
+    let mut soft_nodes = None;
 
     let mut mode = "";
 
     for cmd in commands {
         match *cmd {
             "nodes" => mode = "nodes",
+            "softnodes" => mode = "softnodes",
             "movetime" => mode = "movetime",
             "depth" => mode = "depth",
             "wtime" => mode = "wtime",
@@ -246,15 +495,21 @@ fn go<T: GameRep>(
             "winc" => mode = "winc",
             "binc" => mode = "binc",
             "movestogo" => mode = "movestogo",
+            "mate" => mode = "mate",
+            "searchmoves" => mode = "searchmoves",
+            "infinite" => infinite = true,
             _ => match mode {
                 "nodes" => max_nodes = cmd.parse().unwrap_or(max_nodes),
+                "softnodes" => soft_nodes = cmd.parse().ok(),
                 "movetime" => max_time = cmd.parse().ok(),
                 "depth" => max_depth = cmd.parse().unwrap_or(max_depth),
                 "wtime" => times[0] = Some(cmd.parse().unwrap_or(0)),
                 "btime" => times[1] = Some(cmd.parse().unwrap_or(0)),
                 "winc" => incs[0] = Some(cmd.parse().unwrap_or(0)),
                 "binc" => incs[1] = Some(cmd.parse().unwrap_or(0)),
-                "movestogo" => movestogo = cmd.parse().unwrap_or(30),
+                "movestogo" => movestogo = cmd.parse().ok(),
+                "mate" => max_mate = cmd.parse().ok(),
+                "searchmoves" => search_moves.extend(pos.parse_move(cmd)),
                 _ => mode = "none",
             },
         }
@@ -262,15 +517,9 @@ fn go<T: GameRep>(
 
     let mut time = None;
 
-    // `go wtime <wtime> btime <btime> winc <winc> binc <binc>``
+    // `go wtime <wtime> btime <btime> winc <winc> binc <binc> movestogo <moves>`
     if let Some(t) = times[pos.tm_stm()] {
-        let mut base = t / movestogo.max(1);
-
-        if let Some(i) = incs[pos.tm_stm()] {
-            base += i * 3 / 4;
-        }
-
-        time = Some(base);
+        time = Some(Limits::move_time(t, incs[pos.tm_stm()], movestogo));
     }
 
     // `go movetime <time>`
@@ -285,14 +534,22 @@ fn go<T: GameRep>(
     }
 
     let mut searcher = Searcher::new(pos.clone(), tree, params.clone(), policy, value);
+    searcher.set_move_selection(move_selection);
+    searcher.set_search_moves(&search_moves);
+    searcher.set_abort_signal(&signals.abort);
 
     let limits = Limits {
         max_time: time,
         max_depth,
         max_nodes,
+        max_mate,
+        infinite,
+        soft_nodes,
     };
 
+    signals.start_search();
     let (mov, _) = searcher.search(limits, true, &mut 0, &prev);
+    signals.finish_search();
 
     println!("bestmove {}", pos.conv_mov_to_str(mov));
 
@@ -303,15 +560,53 @@ fn go<T: GameRep>(
     searcher.tree_and_board()
 }
 
+/// Handles `perft <depth> [divide] [hash] [nobulk]`.
 fn run_perft<T: GameRep>(commands: &[&str], pos: &T) {
-    let depth = commands[1].parse().unwrap();
-    let root_pos = pos.clone();
+    let Some(depth) = commands.get(1).and_then(|depth| depth.parse().ok()) else {
+        println!("info string usage: perft <depth> [divide] [hash] [nobulk]");
+        return;
+    };
+
+    let options = &commands[2..];
+    let divide = options.contains(&"divide");
+    let hash = options.contains(&"hash");
+    let bulk = !options.contains(&"nobulk");
+
+    let mut root_pos = pos.clone();
     let now = Instant::now();
-    let count = root_pos.perft(depth);
-    let time = now.elapsed().as_micros();
-    println!(
+
+    let result = if divide {
+        let mut total = PerftResult::default();
+
+        for (mov, result) in games::divide(&mut root_pos, depth, bulk, hash) {
+            let mov = root_pos.conv_mov_to_str(mov);
+
+            if hash {
+                println!("{mov}: {} hash {:016x}", result.nodes, result.checksum);
+            } else {
+                println!("{mov}: {}", result.nodes);
+            }
+
+            total += result;
+        }
+
+        total
+    } else {
+        games::perft(&mut root_pos, depth, bulk, hash)
+    };
+
+    let time = now.elapsed().as_micros().max(1);
+    let count = result.nodes;
+
+    print!(
         "perft {depth} time {} nodes {count} ({:.2} Mnps)",
         time / 1000,
         count as f32 / time as f32
     );
+
+    if hash {
+        print!(" hash {:016x}", result.checksum);
+    }
+
+    println!();
 }
@@ -0,0 +1,174 @@
+use crate::{
+    games::GameRep,
+    mcts::{Limits, Searcher},
+    Tree,
+};
+
+use std::{fs::File, io::Write, time::Instant};
+
+/// A test position, with the moves that solve it (`bm`)
+/// or that must be avoided (`am`), as given in SAN.
+struct EpdEntry {
+    id: String,
+    fen: String,
+    best: Vec<String>,
+    avoid: Vec<String>,
+}
+
+impl EpdEntry {
+    fn parse(line: &str, index: usize) -> Option<Self> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+
+        if fields.len() < 4 {
+            return None;
+        }
+
+        // EPDs have no move counters
+        let fen = format!("{} 0 1", fields[..4].join(" "));
+
+        let mut entry = Self {
+            id: format!("{index}"),
+            fen,
+            best: Vec::new(),
+            avoid: Vec::new(),
+        };
+
+        let opcodes = fields[4..].join(" ");
+
+        for opcode in opcodes.split(';') {
+            let opcode = opcode.trim();
+            let (name, operands) = opcode.split_once(' ').unwrap_or((opcode, ""));
+            let operands = operands.split_whitespace().map(String::from);
+
+            match name {
+                "bm" => entry.best.extend(operands),
+                "am" => entry.avoid.extend(operands),
+                "id" => entry.id = operands.collect::<Vec<_>>().join(" ").replace('"', ""),
+                _ => {}
+            }
+        }
+
+        Some(entry)
+    }
+}
+
+/// Moves in an EPD are normally given in SAN, but some suites use UCI notation.
+fn parse_moves<T: GameRep>(pos: &T, moves: &[String]) -> Option<Vec<u16>> {
+    moves
+        .iter()
+        .map(|mov| {
+            pos.parse_san(mov)
+                .or_else(|| pos.parse_move(mov))
+                .map(Into::into)
+        })
+        .collect()
+}
+
+/// Searches every position in the EPD file at `path`, reporting which are
+/// solved and how long it took, and writing the results to `csv` if given.
+pub fn run<T: GameRep>(
+    path: &str,
+    limits: Limits,
+    csv: Option<&str>,
+    policy: &T::Policy,
+    value: &T::Value,
+) {
+    let suite = std::fs::read_to_string(path).expect("Provide a correct path!");
+    let mut csv = csv.map(|path| File::create(path).expect("Provide a correct path!"));
+
+    if let Some(out) = &mut csv {
+        writeln!(
+            out,
+            "id,fen,bm,am,move,solved,nodes,time_ms,solved_nodes,solved_ms"
+        )
+        .unwrap();
+    }
+
+    let params = T::default_mcts_params();
+    let mut tree = Tree::new_mb(64);
+
+    let mut solved = 0;
+    let mut total = 0;
+    let mut total_time = 0;
+
+    let lines = suite.lines().filter(|line| !line.trim().is_empty());
+
+    for (i, line) in lines.enumerate() {
+        let entry = EpdEntry::parse(line, i + 1);
+
+        let Some(entry) = entry else {
+            println!("{}: could not parse '{line}'", i + 1);
+            continue;
+        };
+
+        if entry.best.is_empty() && entry.avoid.is_empty() {
+            println!("{}: no bm or am, skipping", entry.id);
+            continue;
+        }
+
+        let pos = match T::try_from_fen(&entry.fen) {
+            Ok(pos) => pos,
+            Err(err) => {
+                println!("{}: invalid position: {err}", entry.id);
+                continue;
+            }
+        };
+
+        let (Some(best), Some(avoid)) = (
+            parse_moves(&pos, &entry.best),
+            parse_moves(&pos, &entry.avoid),
+        ) else {
+            println!("{}: illegal bm or am move", entry.id);
+            continue;
+        };
+
+        tree.clear();
+
+        let mut searcher = Searcher::new(pos.clone(), tree, params.clone(), policy, value);
+        searcher.set_track_best_move();
+        let timer = Instant::now();
+        let mut nodes = 0;
+
+        let (mov, _) = searcher.search(limits, false, &mut nodes, &None);
+
+        let time = timer.elapsed().as_millis();
+        let (solved_ms, solved_nodes) = searcher.best_move_found();
+        tree = searcher.tree_and_board().0;
+
+        let mov_u16 = mov.into();
+        let is_solved = (best.is_empty() || best.contains(&mov_u16)) && !avoid.contains(&mov_u16);
+        let san = pos.conv_mov_to_san(mov);
+
+        total += 1;
+
+        if is_solved {
+            solved += 1;
+            total_time += solved_ms;
+            println!("{}: solved with {san} in {solved_ms}ms", entry.id);
+        } else {
+            println!("{}: failed, played {san}", entry.id);
+        }
+
+        if let Some(out) = &mut csv {
+            let (solved_nodes, solved_ms) = if is_solved {
+                (solved_nodes.to_string(), solved_ms.to_string())
+            } else {
+                (String::new(), String::new())
+            };
+
+            writeln!(
+                out,
+                "\"{}\",{},{},{},{san},{is_solved},{nodes},{time},{solved_nodes},{solved_ms}",
+                entry.id,
+                entry.fen,
+                entry.best.join(" "),
+                entry.avoid.join(" "),
+            )
+            .unwrap();
+        }
+    }
+
+    let average = total_time.checked_div(solved).unwrap_or(0);
+    println!("solved {solved}/{total}, failed {}", total - solved);
+    println!("average time to solution {average}ms");
+}
@@ -1,7 +1,11 @@
 pub mod ataxx;
 pub mod chess;
+mod perft;
+mod san;
 pub mod shatranj;
 
+pub(crate) use self::perft::{divide, perft, PerftResult};
+
 use crate::MctsParams;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
@@ -13,6 +17,64 @@ pub enum GameState {
     Won(u8),
 }
 
+/// Hashes of the positions played before the current one.
+#[derive(Clone, Default)]
+pub struct HashStack {
+    hashes: Vec<u64>,
+    // hashes before this are of positions from before the last
+    // irreversible move, so can't be repeated, but the buffer
+    // is kept so that unmaking the move doesn't reallocate
+    start: usize,
+}
+
+impl HashStack {
+    /// Hashes of the positions since the last irreversible move,
+    /// which are the only ones that can be repeated.
+    pub fn reversible(&self) -> &[u64] {
+        &self.hashes[self.start..]
+    }
+
+    /// Records the position a move was made from, given its hash.
+    fn push(&mut self, hash: u64, irreversible: bool) {
+        self.hashes.push(hash);
+
+        if irreversible {
+            self.start = self.hashes.len();
+        }
+    }
+}
+
+/// What `unmake_move` needs to restore the position from before a move.
+pub struct Undo<B> {
+    board: B,
+    len: usize,
+    start: usize,
+}
+
+impl<B> Undo<B> {
+    /// Taken before a move from `board` is made.
+    fn new(board: B, stack: &HashStack) -> Self {
+        Self {
+            board,
+            len: stack.hashes.len(),
+            start: stack.start,
+        }
+    }
+
+    /// Restores `stack` and returns the board from before the move.
+    fn restore(self, stack: &mut HashStack) -> B {
+        stack.hashes.truncate(self.len);
+        stack.start = self.start;
+        self.board
+    }
+}
+
+/// Maps a win probability to a centipawn score, with `scale`
+/// centipawns per unit of the logit of the win probability.
+pub fn wdl_to_cp(wdl: f32, scale: f32) -> f32 {
+    -scale * (1.0 / wdl.clamp(0.0, 1.0) - 1.0).ln()
+}
+
 impl std::fmt::Display for GameState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -24,6 +86,90 @@ impl std::fmt::Display for GameState {
     }
 }
 
+/// Why a FEN string was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    MissingField(&'static str),
+    WrongRankCount(usize),
+    InvalidRank(String),
+    InvalidPiece(char),
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    InvalidEnPassant(String),
+    InvalidCounter(String),
+    KingCount { side: usize, count: u32 },
+    PawnOnBackRank,
+    OpponentInCheck,
+    CastlingWithoutRook(char),
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {field}"),
+            FenError::WrongRankCount(n) => write!(f, "expected a board of {n} ranks"),
+            FenError::InvalidRank(rank) => write!(f, "rank '{rank}' has the wrong length"),
+            FenError::InvalidPiece(ch) => write!(f, "invalid piece '{ch}'"),
+            FenError::InvalidSideToMove(stm) => write!(f, "invalid side to move '{stm}'"),
+            FenError::InvalidCastling(rights) => write!(f, "invalid castling rights '{rights}'"),
+            FenError::InvalidEnPassant(sq) => write!(f, "invalid en passant square '{sq}'"),
+            FenError::InvalidCounter(counter) => write!(f, "invalid move counter '{counter}'"),
+            FenError::KingCount { side, count } => {
+                let side = ["white", "black"][*side];
+                write!(f, "{side} has {count} kings")
+            }
+            FenError::PawnOnBackRank => write!(f, "pawn on first or last rank"),
+            FenError::OpponentInCheck => write!(f, "side not to move is in check"),
+            FenError::CastlingWithoutRook(ch) => {
+                write!(f, "castling right '{ch}' without king and rook in place")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+/// Checks the piece placement field of a FEN for a `size` by `size`
+/// board, where `pieces` are the characters that may appear on it.
+pub(crate) fn check_placement(placement: &str, size: usize, pieces: &str) -> Result<(), FenError> {
+    let ranks = placement.split('/').collect::<Vec<_>>();
+
+    if ranks.len() != size {
+        return Err(FenError::WrongRankCount(ranks.len()));
+    }
+
+    for rank in ranks {
+        let mut squares = 0;
+
+        for ch in rank.chars() {
+            if let Some(empty) = ch.to_digit(10).filter(|&n| n > 0) {
+                squares += empty as usize;
+            } else if pieces.contains(ch) {
+                squares += 1;
+            } else {
+                return Err(FenError::InvalidPiece(ch));
+            }
+        }
+
+        if squares != size {
+            return Err(FenError::InvalidRank(rank.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that any move counters at the end of a FEN are numbers.
+pub(crate) fn check_counters(counters: &[&str]) -> Result<(), FenError> {
+    for counter in counters.iter().take(2) {
+        if counter.parse::<u32>().is_err() {
+            return Err(FenError::InvalidCounter(counter.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
 pub trait GameRep: Clone + Default + Send + Sync {
     type Move: Copy + Default + From<u16> + Into<u16> + std::fmt::Display;
     type PolicyInputs;
@@ -32,9 +178,17 @@ pub trait GameRep: Clone + Default + Send + Sync {
 
     type Policy: Send + Sync;
     type Value: Send + Sync;
+    type ValueAccumulator: Copy + Default;
+    type Undo;
+
+    /// Centipawns per unit of the logit of the win probability,
+    /// which the value network is trained with.
+    const CP_SCALE: f32;
 
     fn default_mcts_params() -> MctsParams;
 
+    /// Whether two positions are identical, including any
+    /// history that can affect the result of the game.
     fn is_same(&self, other: &Self) -> bool;
 
     fn stm(&self) -> usize;
@@ -48,26 +202,132 @@ pub trait GameRep: Clone + Default + Send + Sync {
 
     fn game_state(&self) -> GameState;
 
-    fn make_move(&mut self, mov: Self::Move);
+    /// Makes `mov`, returning what is needed to undo it, which
+    /// can be ignored if the move won't be unmade.
+    fn make_move(&mut self, mov: Self::Move) -> Self::Undo;
+
+    /// Makes `mov` without recording the position it was made from, so
+    /// repetitions are missed; for callers like perft that only walk the
+    /// move tree. It is undone with `unmake_move` all the same.
+    fn make_move_untracked(&mut self, mov: Self::Move) -> Self::Undo;
+
+    /// Undoes the last move made, given what `make_move` returned for it.
+    fn unmake_move(&mut self, undo: Self::Undo);
 
     fn map_legal_moves<F: FnMut(Self::Move)>(&self, f: F);
 
-    fn get_policy_feats(&self) -> Self::PolicyInputs;
+    fn get_policy_feats(&self, policy: &Self::Policy) -> Self::PolicyInputs;
 
     fn get_policy(&self, mov: Self::Move, feats: &Self::PolicyInputs, policy: &Self::Policy)
         -> f32;
 
+    /// Calls `f` with every legal move, in `map_legal_moves` order, and
+    /// its policy logit, which games can override to share work between
+    /// the moves of a position.
+    fn map_policies<F: FnMut(Self::Move, f32)>(&self, policy: &Self::Policy, mut f: F) {
+        let feats = self.get_policy_feats(policy);
+        self.map_legal_moves(|mov| f(mov, self.get_policy(mov, &feats, policy)));
+    }
+
     fn get_value(&self, value: &Self::Value) -> i32;
 
     fn get_value_wdl(&self, value: &Self::Value) -> f32 {
-        1.0 / (1.0 + (-(self.get_value(value) as f32) / 400.0).exp())
+        Self::cp_to_wdl(self.get_value(value) as f32)
+    }
+
+    fn refresh_value_accumulator(&self, acc: &mut Self::ValueAccumulator, value: &Self::Value);
+
+    /// Brings `acc` up to date with this position, starting from `base`,
+    /// the accumulator of an earlier position with the same side to move,
+    /// by applying the feature deltas of the moves made since then.
+    fn update_value_accumulator(
+        &self,
+        base: &Self::ValueAccumulator,
+        acc: &mut Self::ValueAccumulator,
+        value: &Self::Value,
+    );
+
+    fn get_value_from_accumulator(&self, acc: &Self::ValueAccumulator, value: &Self::Value) -> i32;
+
+    fn get_value_wdl_from_accumulator(
+        &self,
+        acc: &Self::ValueAccumulator,
+        value: &Self::Value,
+    ) -> f32 {
+        let cp = self.get_value_from_accumulator(acc, value);
+        Self::cp_to_wdl(cp as f32)
+    }
+
+    /// Maps a centipawn score on the scale of the value network to a win probability.
+    fn cp_to_wdl(cp: f32) -> f32 {
+        1.0 / (1.0 + (-cp / Self::CP_SCALE).exp())
     }
 
+    fn as_fen(&self) -> String;
+
+    /// Parses a FEN without checking it, for trusted input.
     fn from_fen(fen: &str) -> Self;
 
+    /// Parses a FEN, rejecting it if it is malformed or
+    /// describes a position that could not occur in a game.
+    fn try_from_fen(fen: &str) -> Result<Self, FenError>;
+
     fn conv_mov_to_str(&self, mov: Self::Move) -> String;
 
-    fn perft(&self, depth: usize) -> u64;
+    /// Standard algebraic notation, for games that have it.
+    fn conv_mov_to_san(&self, mov: Self::Move) -> String {
+        self.conv_mov_to_str(mov)
+    }
+
+    /// Parses a move given in standard algebraic notation,
+    /// returning `None` if it is illegal or ambiguous.
+    fn parse_san(&self, san: &str) -> Option<Self::Move> {
+        self.parse_move(san)
+    }
+
+    /// Parses a move given in the notation of `conv_mov_to_str`,
+    /// returning `None` if it is not a legal move in this position.
+    fn parse_move(&self, mov: &str) -> Option<Self::Move> {
+        let mut found = None;
+
+        self.map_legal_moves(|legal| {
+            if found.is_none() && self.conv_mov_to_str(legal) == mov {
+                found = Some(legal);
+            }
+        });
+
+        found
+    }
 
     fn display(&self, policy: &Self::Policy);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ataxx::Ataxx, chess::Chess, shatranj::Shatranj, wdl_to_cp, GameRep};
+
+    fn wdl_round_trips<T: GameRep>() {
+        assert_eq!(T::cp_to_wdl(0.0), 0.5);
+
+        let logit = T::cp_to_wdl(T::CP_SCALE);
+        assert!((logit - 1.0 / (1.0 + (-1.0f32).exp())).abs() < 1e-6);
+
+        for cp in [-1500.0, -250.0, -1.0, 37.0, 400.0, 900.0] {
+            let wdl = T::cp_to_wdl(cp);
+            assert!(
+                (wdl_to_cp(wdl, T::CP_SCALE) - cp).abs() < 0.5,
+                "{cp} -> {wdl}"
+            );
+        }
+
+        // scores are reported on the scale of the value network unless tuned
+        assert_eq!(T::default_mcts_params().report_scale(), T::CP_SCALE);
+    }
+
+    #[test]
+    fn wdl_scale() {
+        wdl_round_trips::<Chess>();
+        wdl_round_trips::<Shatranj>();
+        wdl_round_trips::<Ataxx>();
+    }
+}
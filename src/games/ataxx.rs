@@ -6,8 +6,9 @@ mod util;
 use goober::SparseVector;
 
 use crate::{
-    value::{ValueFeatureMap, ValueNetwork},
-    GameRep, MctsParams, UciLike,
+    games::{HashStack, Undo},
+    value::{ValueAccumulator, ValueFeatureMap, ValueNetwork},
+    GameRep, GameState, MctsParams, UciLike,
 };
 
 pub use self::{
@@ -22,6 +23,11 @@ impl ValueFeatureMap for Board {
     fn value_feature_map<F: FnMut(usize)>(&self, f: F) {
         self.value_features_map(f);
     }
+
+    fn value_feature_deltas<F: FnMut(usize, bool)>(&self, old: &Self, f: F) -> bool {
+        self.value_features_deltas(old, f);
+        true
+    }
 }
 
 pub struct Uai;
@@ -35,15 +41,28 @@ impl UciLike for Uai {
     fn options() {}
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Default)]
 pub struct Ataxx {
     board: Board,
+    // a single move adds a piece to the board, so no
+    // position from before one can be repeated
+    stack: HashStack,
 }
 
 impl Ataxx {
     pub fn board(&self) -> &Board {
         &self.board
     }
+
+    fn is_threefold(&self) -> bool {
+        let hash = self.board.hash();
+        self.stack
+            .reversible()
+            .iter()
+            .filter(|&&prev| prev == hash)
+            .count()
+            >= 2
+    }
 }
 
 impl From<Ataxx> for Board {
@@ -60,13 +79,18 @@ impl GameRep for Ataxx {
 
     type Policy = PolicyNetwork;
     type Value = ValueNetwork<2916, 256>;
+    type ValueAccumulator = ValueAccumulator<Board, 256>;
+    type Undo = Undo<Board>;
+    const CP_SCALE: f32 = ValueNetwork::<2916, 256>::SCALE as f32;
 
     fn default_mcts_params() -> MctsParams {
-        MctsParams::default()
+        let mut params = MctsParams::default();
+        params.set("report_cp_scale", Self::CP_SCALE / 100.0);
+        params
     }
 
     fn is_same(&self, other: &Self) -> bool {
-        self.board == other.board
+        self.board == other.board && self.stack.reversible() == other.stack.reversible()
     }
 
     fn stm(&self) -> usize {
@@ -81,14 +105,29 @@ impl GameRep for Ataxx {
         mov.uai()
     }
 
+    fn as_fen(&self) -> String {
+        self.board.as_fen()
+    }
+
     fn from_fen(fen: &str) -> Self {
         Self {
             board: Board::from_fen(fen),
+            stack: HashStack::default(),
         }
     }
 
-    fn game_state(&self) -> crate::GameState {
-        self.board.game_state()
+    fn try_from_fen(fen: &str) -> Result<Self, crate::FenError> {
+        Ok(Self {
+            board: Board::try_from_fen(fen)?,
+            stack: HashStack::default(),
+        })
+    }
+
+    fn game_state(&self) -> GameState {
+        match self.board.game_state() {
+            GameState::Ongoing if self.is_threefold() => GameState::Draw,
+            state => state,
+        }
     }
 
     fn map_legal_moves<F: FnMut(Self::Move)>(&self, f: F) {
@@ -99,48 +138,59 @@ impl GameRep for Ataxx {
         value.eval(&self.board)
     }
 
-    fn get_policy_feats(&self) -> SparseVector {
-        self.board.get_features()
+    fn refresh_value_accumulator(&self, acc: &mut Self::ValueAccumulator, value: &Self::Value) {
+        value.refresh(&self.board, acc);
     }
 
-    fn get_policy(&self, mov: Self::Move, feats: &SparseVector, policy: &Self::Policy) -> f32 {
-        policy.get(&mov, feats)
+    fn update_value_accumulator(
+        &self,
+        base: &Self::ValueAccumulator,
+        acc: &mut Self::ValueAccumulator,
+        value: &Self::Value,
+    ) {
+        value.update(&self.board, base, acc);
     }
 
-    fn make_move(&mut self, mov: Self::Move) {
-        self.board.make(mov);
+    fn get_value_from_accumulator(&self, acc: &Self::ValueAccumulator, value: &Self::Value) -> i32 {
+        value.eval_accumulator(acc)
     }
 
-    fn perft(&self, depth: usize) -> u64 {
-        perft(&self.board, depth as u8)
+    fn get_policy_feats(&self, _: &Self::Policy) -> SparseVector {
+        self.board.get_features()
     }
 
-    fn hash(&self) -> u64 {
-        use self::util::ZVALS;
+    fn get_policy(&self, mov: Self::Move, feats: &SparseVector, policy: &Self::Policy) -> f32 {
+        policy.get(&mov, feats)
+    }
 
-        let mut hash = 0;
+    fn make_move(&mut self, mov: Self::Move) -> Self::Undo {
+        let board = self.board;
+        let undo = Undo::new(board, &self.stack);
+        self.board.make(mov);
 
-        let mut boys = self.board.boys();
-        while boys > 0 {
-            let sq = boys.trailing_zeros() as usize;
-            boys &= boys - 1;
+        let single = self.board.occ().count_ones() != board.occ().count_ones();
+        self.stack.push(board.hash(), single);
 
-            hash ^= ZVALS[0][sq];
-        }
+        undo
+    }
 
-        let mut opps = self.board.opps();
-        while opps > 0 {
-            let sq = opps.trailing_zeros() as usize;
-            opps &= opps - 1;
+    fn make_move_untracked(&mut self, mov: Self::Move) -> Self::Undo {
+        let undo = Undo::new(self.board, &self.stack);
+        self.board.make(mov);
 
-            hash ^= ZVALS[1][sq];
-        }
+        undo
+    }
 
-        hash
+    fn unmake_move(&mut self, undo: Self::Undo) {
+        self.board = undo.restore(&mut self.stack);
+    }
+
+    fn hash(&self) -> u64 {
+        self.board.hash()
     }
 
     fn display(&self, policy: &Self::Policy) {
-        let feats = self.get_policy_feats();
+        let feats = self.get_policy_feats(policy);
         let mut moves = Vec::new();
         let mut max = f32::NEG_INFINITY;
         self.map_legal_moves(|mov| {
@@ -218,21 +268,119 @@ impl GameRep for Ataxx {
     }
 }
 
-fn perft(board: &Board, depth: u8) -> u64 {
-    if depth == 1 {
-        return board.movegen_bulk(false);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incremental_hash_matches_from_scratch() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+
+        for fen in Uai::FEN_STRING.lines() {
+            for _ in 0..4 {
+                let mut pos = Ataxx::from_fen(fen);
+
+                // both sides can be left only able to pass
+                for _ in 0..512 {
+                    assert_eq!(pos.board.hash(), pos.board.hash_from_scratch(), "{fen}");
+
+                    let mut moves = Vec::new();
+                    pos.map_legal_moves(|mov| moves.push(mov));
+
+                    if moves.is_empty() {
+                        break;
+                    }
+
+                    pos.make_move(moves[rand() % moves.len()]);
+                }
+            }
+        }
     }
 
-    let mut nodes = 0;
+    #[test]
+    fn threefold_repetition() {
+        let mut pos = Ataxx::from_fen(STARTPOS);
+
+        let mut undo = None;
+
+        for cycle in 0..2 {
+            assert_eq!(pos.game_state(), GameState::Ongoing);
+
+            for mov in ["g1e1", "a1c1", "e1g1", "c1a1"] {
+                let mov = pos.parse_move(mov).unwrap();
+                undo = Some(pos.make_move(mov));
+            }
+
+            let expected = [GameState::Ongoing, GameState::Draw][cycle];
+            assert_eq!(pos.game_state(), expected);
+        }
+
+        pos.unmake_move(undo.unwrap());
+        assert_eq!(pos.game_state(), GameState::Ongoing);
+    }
 
-    board.map_legal_moves(|mov| {
-        let mut new = *board;
+    #[test]
+    fn unmake_move_restores_position() {
+        let mut pos = Ataxx::from_fen("x5o/7/7/7/7/7/o5x x 0 1");
 
-        if !mov.is_pass() {
-            new.make(mov);
-            nodes += perft(&new, depth - 1);
+        // leave hashes on the stack for irreversible moves to clear
+        for mov in ["g1e1", "a1c1"] {
+            pos.make_move(pos.parse_move(mov).unwrap());
         }
-    });
 
-    nodes
+        let before = pos.clone();
+        let mut moves = Vec::new();
+        pos.map_legal_moves(|mov| moves.push(mov));
+
+        for mov in moves {
+            let undo = pos.make_move(mov);
+
+            let mut replies = Vec::new();
+            pos.map_legal_moves(|reply| replies.push(reply));
+
+            for reply in replies {
+                let undo = pos.make_move(reply);
+                pos.unmake_move(undo);
+            }
+
+            pos.unmake_move(undo);
+
+            assert_eq!(pos.as_fen(), before.as_fen());
+            assert_eq!(pos.stack.reversible(), before.stack.reversible());
+            assert!(pos.is_same(&before));
+        }
+    }
+
+    #[test]
+    fn single_move_clears_repetitions() {
+        let mut pos = Ataxx::from_fen(STARTPOS);
+
+        for mov in ["g1e1", "a1c1", "e1g1", "c1a1", "g2", "a2", "g2e2", "a2c2"] {
+            let mov = pos.parse_move(mov).unwrap();
+            pos.make_move(mov);
+        }
+
+        assert_eq!(pos.stack.reversible().len(), 2);
+        assert_eq!(pos.game_state(), GameState::Ongoing);
+    }
+
+    #[test]
+    fn hash_includes_side_to_move_and_gaps() {
+        let red = Ataxx::from_fen(STARTPOS);
+        let blue = Ataxx::from_fen("x5o/7/7/7/7/7/o5x o 0 1");
+        let gaps = Ataxx::from_fen("x5o/7/3-3/2-1-2/3-3/7/o5x x 0 1");
+
+        assert_ne!(red.hash(), blue.hash());
+        assert_ne!(red.hash(), gaps.hash());
+
+        let mut passed = red.clone();
+        passed.make_move(Move::new_pass());
+        assert_eq!(passed.hash(), blue.hash());
+    }
 }
@@ -1,20 +1,27 @@
-use crate::GameState;
+use crate::{
+    games::{check_counters, check_placement, FenError},
+    GameState,
+};
 
 use goober::SparseVector;
 
 use super::{
     moves::Move,
-    util::{Bitboard, Side},
+    util::{Bitboard, Side, ZVALS},
     STARTPOS,
 };
 
 use std::{cmp::Ordering, fmt::Display};
 
+// squares covered by a tuple, relative to its lowest square
+const TUPLE_MASK: u64 = 0b0001_1000_0011;
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Board {
     bbs: [u64; 2],
     gaps: u64,
+    hash: u64,
     stm: bool,
     halfm: u8,
     fullm: u16,
@@ -27,6 +34,21 @@ impl Default for Board {
 }
 
 impl Board {
+    pub fn from_raw(bbs: [u64; 3], stm: bool, halfm: u8, fullm: u16) -> Self {
+        let mut board = Self {
+            bbs: [bbs[0], bbs[1]],
+            gaps: bbs[2],
+            hash: 0,
+            stm,
+            halfm,
+            fullm,
+        };
+
+        board.hash = board.hash_from_scratch();
+
+        board
+    }
+
     pub fn stm(&self) -> usize {
         usize::from(self.stm)
     }
@@ -55,6 +77,36 @@ impl Board {
         self.fullm
     }
 
+    /// Zobrist key of the pieces, gaps and side to move. The half-move
+    /// clock is left out so that repeated positions share a key.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub(crate) fn hash_from_scratch(&self) -> u64 {
+        let mut hash = if self.stm { ZVALS.c } else { 0 };
+
+        for (side, &bb) in self.bbs.iter().enumerate() {
+            let mut bb = bb;
+            while bb > 0 {
+                let sq = bb.trailing_zeros() as usize;
+                bb &= bb - 1;
+
+                hash ^= ZVALS.pcs[side][sq];
+            }
+        }
+
+        let mut gaps = self.gaps;
+        while gaps > 0 {
+            let sq = gaps.trailing_zeros() as usize;
+            gaps &= gaps - 1;
+
+            hash ^= ZVALS.gaps[sq];
+        }
+
+        hash
+    }
+
     pub fn is_hfm_draw(&self, count: u8) -> bool {
         self.halfm() >= count
     }
@@ -69,21 +121,31 @@ impl Board {
 
             if from != 63 {
                 self.bbs[stm] ^= 1 << from;
+                self.hash ^= ZVALS.pcs[stm][from];
                 self.halfm += 1;
             } else {
                 self.halfm = 0;
             }
 
             self.bbs[stm] ^= 1 << to;
+            self.hash ^= ZVALS.pcs[stm][to];
 
             let singles = Bitboard::singles(to);
-            let captures = singles & self.bbs[stm ^ 1];
+            let mut captures = singles & self.bbs[stm ^ 1];
 
             self.bbs[0] ^= captures;
             self.bbs[1] ^= captures;
+
+            while captures > 0 {
+                let sq = captures.trailing_zeros() as usize;
+                captures &= captures - 1;
+
+                self.hash ^= ZVALS.pcs[0][sq] ^ ZVALS.pcs[1][sq];
+            }
         }
 
         self.stm = !self.stm;
+        self.hash ^= ZVALS.c;
     }
 
     pub fn game_over(&self) -> bool {
@@ -165,47 +227,68 @@ impl Board {
     }
 
     pub fn value_features_map<F: FnMut(usize)>(&self, mut f: F) {
-        const PER_TUPLE: usize = 3usize.pow(4);
-        const POWERS: [usize; 4] = [1, 3, 9, 27];
-        const MASK: u64 = 0b0001_1000_0011;
+        let boys = self.boys();
+        let opps = self.opps();
+
+        for tuple in 0..36 {
+            f(Self::tuple_feature(boys, opps, tuple));
+        }
+    }
 
+    /// Calls `f(feat, add)` for each feature gained or lost since `old`,
+    /// which must have the same side to move.
+    pub fn value_features_deltas<F: FnMut(usize, bool)>(&self, old: &Self, mut f: F) {
         let boys = self.boys();
         let opps = self.opps();
+        let changed = (boys ^ old.boys()) | (opps ^ old.opps());
 
-        for i in 0..6 {
-            for j in 0..6 {
-                let tuple = 6 * i + j;
-                let mut feat = PER_TUPLE * tuple;
+        for tuple in 0..36 {
+            if (changed >> Self::tuple_offset(tuple)) & TUPLE_MASK > 0 {
+                f(Self::tuple_feature(old.boys(), old.opps(), tuple), false);
+                f(Self::tuple_feature(boys, opps, tuple), true);
+            }
+        }
+    }
 
-                let offset = 7 * i + j;
-                let mut b = (boys >> offset) & MASK;
-                let mut o = (opps >> offset) & MASK;
+    fn tuple_offset(tuple: usize) -> usize {
+        7 * (tuple / 6) + tuple % 6
+    }
 
-                while b > 0 {
-                    let mut sq = b.trailing_zeros() as usize;
-                    if sq > 6 {
-                        sq -= 5;
-                    }
+    /// Each 2x2 square of the board is a tuple, whose feature
+    /// encodes the state of all 4 of its squares.
+    fn tuple_feature(boys: u64, opps: u64, tuple: usize) -> usize {
+        const PER_TUPLE: usize = 3usize.pow(4);
+        const POWERS: [usize; 4] = [1, 3, 9, 27];
 
-                    feat += POWERS[sq];
+        let mut feat = PER_TUPLE * tuple;
 
-                    b &= b - 1;
-                }
+        let offset = Self::tuple_offset(tuple);
+        let mut b = (boys >> offset) & TUPLE_MASK;
+        let mut o = (opps >> offset) & TUPLE_MASK;
 
-                while o > 0 {
-                    let mut sq = o.trailing_zeros() as usize;
-                    if sq > 6 {
-                        sq -= 5;
-                    }
+        while b > 0 {
+            let mut sq = b.trailing_zeros() as usize;
+            if sq > 6 {
+                sq -= 5;
+            }
 
-                    feat += 2 * POWERS[sq];
+            feat += POWERS[sq];
 
-                    o &= o - 1;
-                }
+            b &= b - 1;
+        }
 
-                f(feat);
+        while o > 0 {
+            let mut sq = o.trailing_zeros() as usize;
+            if sq > 6 {
+                sq -= 5;
             }
+
+            feat += 2 * POWERS[sq];
+
+            o &= o - 1;
         }
+
+        feat
     }
 
     pub fn get_features(&self) -> SparseVector {
@@ -216,28 +299,6 @@ impl Board {
         feats
     }
 
-    #[cfg(not(feature = "datagen"))]
-    pub fn movegen_bulk(&self, pass: bool) -> u64 {
-        let mut moves = u64::from(pass);
-
-        let occ = self.occ();
-        let nocc = Bitboard::not(occ);
-        let mut boys = self.boys();
-
-        let singles = Bitboard::expand(boys) & nocc;
-        moves += u64::from(singles.count_ones());
-
-        while boys > 0 {
-            let from = boys.trailing_zeros();
-            boys &= boys - 1;
-
-            let doubles = Bitboard::doubles(from as usize) & nocc;
-            moves += u64::from(doubles.count_ones());
-        }
-
-        moves
-    }
-
     pub fn as_fen(&self) -> String {
         let mut fen = String::new();
 
@@ -312,13 +373,28 @@ impl Board {
             }
         }
 
-        Self {
-            bbs,
-            gaps,
-            stm,
-            halfm,
-            fullm,
+        Self::from_raw([bbs[0], bbs[1], gaps], stm, halfm, fullm)
+    }
+
+    pub fn try_from_fen(fen: &str) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+
+        let placement = fields
+            .first()
+            .ok_or(FenError::MissingField("piece placement"))?;
+        let stm = fields
+            .get(1)
+            .ok_or(FenError::MissingField("side to move"))?;
+
+        check_placement(placement, 7, "xoXO-")?;
+
+        if *stm != "x" && *stm != "o" {
+            return Err(FenError::InvalidSideToMove(stm.to_string()));
         }
+
+        check_counters(&fields[2..])?;
+
+        Ok(Self::from_fen(fen))
     }
 }
 
@@ -72,12 +72,27 @@ const fn rand(mut seed: u64) -> u64 {
     seed
 }
 
-pub static ZVALS: [[u64; 49]; 2] = {
+pub struct ZobristVals {
+    pub pcs: [[u64; 49]; 2],
+    pub gaps: [u64; 49],
+    pub c: u64,
+}
+
+pub static ZVALS: ZobristVals = {
     let mut seed = 180_620_142;
     seed = rand(seed);
 
-    init!(|side, 2| init!(|sq, 49| {
+    let pcs = init!(|side, 2| init!(|sq, 49| {
+        seed = rand(seed);
+        seed
+    }));
+
+    let gaps = init!(|sq, 49| {
         seed = rand(seed);
         seed
-    }))
+    });
+
+    seed = rand(seed);
+
+    ZobristVals { pcs, gaps, c: seed }
 };
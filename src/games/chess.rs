@@ -4,11 +4,13 @@ mod consts;
 mod frc;
 mod moves;
 mod policy;
+mod san;
 mod value;
 
 use crate::{
     comm::UciLike,
-    games::{GameRep, GameState},
+    games::{FenError, GameRep, GameState, HashStack, Undo},
+    value::ValueFeatureMap,
     MctsParams,
 };
 
@@ -16,12 +18,22 @@ pub use self::{
     board::Board,
     frc::Castling,
     moves::Move,
-    policy::{PolicyNetwork, SubNet},
-    value::ValueNetwork,
+    policy::{PolicyFeats, PolicyNetwork, QuantisedPolicyNetwork, SubNet},
+    value::{ValueAccumulator, ValueNetwork},
 };
 
 const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
+impl ValueFeatureMap for Board {
+    fn value_feature_map<F: FnMut(usize)>(&self, f: F) {
+        self.map_value_features(f);
+    }
+
+    fn value_feature_deltas<F: FnMut(usize, bool)>(&self, old: &Self, f: F) -> bool {
+        self.map_value_feature_deltas(old, f)
+    }
+}
+
 pub struct Uci;
 impl UciLike for Uci {
     const NAME: &'static str = "uci";
@@ -40,7 +52,7 @@ impl UciLike for Uci {
 pub struct Chess {
     board: Board,
     castling: Castling,
-    stack: Vec<u64>,
+    stack: HashStack,
 }
 
 impl Default for Chess {
@@ -51,7 +63,7 @@ impl Default for Chess {
         Self {
             board,
             castling,
-            stack: Vec::new(),
+            stack: HashStack::default(),
         }
     }
 }
@@ -72,10 +84,13 @@ impl Chess {
 
 impl GameRep for Chess {
     type Move = Move;
-    type PolicyInputs = (goober::SparseVector, u64);
+    type PolicyInputs = PolicyFeats;
 
-    type Policy = PolicyNetwork;
+    type Policy = QuantisedPolicyNetwork;
     type Value = ValueNetwork;
+    type ValueAccumulator = ValueAccumulator;
+    type Undo = Undo<Board>;
+    const CP_SCALE: f32 = ValueNetwork::SCALE as f32;
 
     const STARTPOS: &'static str = STARTPOS;
 
@@ -87,17 +102,50 @@ impl GameRep for Chess {
         params.set("cpuct", 0.65);
         params.set("cpuct_var_weight", 0.85);
         params.set("cpuct_var_scale", 0.2);
+        params.set("report_cp_scale", Self::CP_SCALE / 100.0);
         params
     }
 
     fn is_same(&self, other: &Self) -> bool {
-        self.board == other.board
+        self.board == other.board && self.stack.reversible() == other.stack.reversible()
     }
 
     fn conv_mov_to_str(&self, mov: Self::Move) -> String {
         mov.to_uci(&self.castling)
     }
 
+    fn conv_mov_to_san(&self, mov: Self::Move) -> String {
+        self.board.to_san(mov, &self.castling)
+    }
+
+    fn parse_san(&self, san: &str) -> Option<Self::Move> {
+        self.board.parse_san(san, &self.castling)
+    }
+
+    /// Castling is accepted in both standard and chess960 notation,
+    /// whichever mode the position is in.
+    fn parse_move(&self, mov: &str) -> Option<Self::Move> {
+        let mut exact = None;
+        let mut castle = None;
+
+        self.map_legal_moves(|legal| {
+            if legal.to_uci(&self.castling) == mov {
+                exact = Some(legal);
+            } else if legal.is_castle()
+                && legal.to_uci_with(&self.castling, !self.castling.is_chess960()) == mov
+            {
+                castle = Some(legal);
+            }
+        });
+
+        // in chess960 a king step can share the notation of a castle
+        exact.or(castle)
+    }
+
+    fn as_fen(&self) -> String {
+        self.board.as_fen(&self.castling)
+    }
+
     fn from_fen(fen: &str) -> Self {
         let mut castling = Castling::default();
         let board = Board::parse_fen(fen, &mut castling);
@@ -105,29 +153,52 @@ impl GameRep for Chess {
         Self {
             board,
             castling,
-            stack: Vec::new(),
+            stack: HashStack::default(),
         }
     }
 
+    fn try_from_fen(fen: &str) -> Result<Self, FenError> {
+        let mut castling = Castling::default();
+        let board = Board::try_parse_fen(fen, &mut castling)?;
+
+        Ok(Self {
+            board,
+            castling,
+            stack: HashStack::default(),
+        })
+    }
+
     fn map_legal_moves<F: FnMut(Self::Move)>(&self, f: F) {
         self.board.map_legal_moves(&self.castling, f);
     }
 
     fn game_state(&self) -> GameState {
-        self.board.game_state(&self.castling, &self.stack)
+        self.board
+            .game_state(&self.castling, self.stack.reversible())
     }
 
     fn hash(&self) -> u64 {
         self.board.hash()
     }
 
-    fn make_move(&mut self, mov: Self::Move) {
-        self.stack.push(self.board.hash());
+    fn make_move(&mut self, mov: Self::Move) -> Self::Undo {
+        let board = self.board;
+        let undo = Undo::new(board, &self.stack);
         self.board.make(mov, &self.castling);
+        self.stack.push(board.hash(), self.board.halfm() == 0);
 
-        if self.board.halfm() == 0 {
-            self.stack.clear();
-        }
+        undo
+    }
+
+    fn make_move_untracked(&mut self, mov: Self::Move) -> Self::Undo {
+        let undo = Undo::new(self.board, &self.stack);
+        self.board.make(mov, &self.castling);
+
+        undo
+    }
+
+    fn unmake_move(&mut self, undo: Self::Undo) {
+        self.board = undo.restore(&mut self.stack);
     }
 
     fn stm(&self) -> usize {
@@ -138,35 +209,43 @@ impl GameRep for Chess {
         self.stm()
     }
 
-    fn get_policy_feats(&self) -> Self::PolicyInputs {
-        let mut feats = goober::SparseVector::with_capacity(32);
-        self.board.map_policy_features(|feat| feats.push(feat));
-        (feats, self.board.threats())
+    fn get_policy_feats(&self, _: &Self::Policy) -> Self::PolicyInputs {
+        QuantisedPolicyNetwork::feats(&self.board)
     }
 
-    fn get_policy(
-        &self,
-        mov: Self::Move,
-        (feats, threats): &Self::PolicyInputs,
-        policy: &Self::Policy,
-    ) -> f32 {
-        policy.get(&self.board, &mov, feats, *threats)
+    fn get_policy(&self, mov: Self::Move, feats: &PolicyFeats, policy: &Self::Policy) -> f32 {
+        policy.get(&self.board, &mov, feats)
+    }
+
+    fn map_policies<F: FnMut(Self::Move, f32)>(&self, policy: &Self::Policy, f: F) {
+        policy.map_policies(&self.board, &self.castling, f);
     }
 
     fn get_value(&self, value: &Self::Value) -> i32 {
         value.eval(&self.board)
     }
 
-    fn perft(&self, depth: usize) -> u64 {
-        perft::<true, true>(&self.board, depth as u8, &self.castling)
+    fn refresh_value_accumulator(&self, acc: &mut Self::ValueAccumulator, value: &Self::Value) {
+        value.refresh(&self.board, acc);
     }
 
-    fn display(&self, policy: &PolicyNetwork) {
-        let feats = self.get_policy_feats();
+    fn update_value_accumulator(
+        &self,
+        base: &Self::ValueAccumulator,
+        acc: &mut Self::ValueAccumulator,
+        value: &Self::Value,
+    ) {
+        value.update(&self.board, base, acc);
+    }
+
+    fn get_value_from_accumulator(&self, acc: &Self::ValueAccumulator, value: &Self::Value) -> i32 {
+        value.eval_accumulator(acc)
+    }
+
+    fn display(&self, policy: &Self::Policy) {
         let mut moves = Vec::new();
         let mut max = f32::NEG_INFINITY;
-        self.map_legal_moves(|mov| {
-            let policy = self.get_policy(mov, &feats, policy);
+        self.map_policies(policy, |mov, policy| {
             moves.push((mov, policy));
 
             if policy > max {
@@ -235,31 +314,109 @@ impl GameRep for Chess {
     }
 }
 
-fn perft<const ROOT: bool, const BULK: bool>(pos: &Board, depth: u8, castling: &Castling) -> u64 {
-    let mut count = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if BULK && !ROOT && depth == 1 {
-        pos.map_legal_moves(castling, |_| count += 1);
-    } else {
-        let leaf = depth == 1;
+    fn state_after(fen: &str, moves: &[&str]) -> GameState {
+        let mut pos = Chess::from_fen(fen);
 
-        pos.map_legal_moves(castling, |mov| {
-            let mut tmp = *pos;
-            tmp.make(mov, castling);
+        for mov in moves {
+            let mov = pos.parse_move(mov).unwrap();
+            pos.make_move(mov);
+        }
 
-            let num = if !BULK && leaf {
-                1
-            } else {
-                perft::<false, BULK>(&tmp, depth - 1, castling)
-            };
+        pos.game_state()
+    }
+
+    const MATE_IN_ONE: &str = "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 99 80";
 
-            count += num;
+    #[test]
+    fn mate_on_hundredth_ply_is_a_loss() {
+        assert_eq!(state_after(MATE_IN_ONE, &["a1a8"]), GameState::Lost(0));
+    }
 
-            if ROOT {
-                println!("{}: {num}", mov.to_uci(castling));
+    #[test]
+    fn fifty_move_rule() {
+        assert_eq!(state_after(MATE_IN_ONE, &[]), GameState::Ongoing);
+        assert_eq!(state_after(MATE_IN_ONE, &["a1a2"]), GameState::Draw);
+        assert_eq!(state_after(MATE_IN_ONE, &["h2h3"]), GameState::Ongoing);
+    }
+
+    #[test]
+    fn stalemate_on_hundredth_ply_is_a_draw() {
+        let fen = "7k/8/4Q3/8/8/8/8/K7 w - - 99 80";
+        assert_eq!(state_after(fen, &["e6f7"]), GameState::Draw);
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let draws = [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1K3 b - - 0 1",
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/B1B1K3 w - - 0 1",
+        ];
+
+        for fen in draws {
+            assert_eq!(state_after(fen, &[]), GameState::Draw, "{fen}");
+        }
+
+        let ongoing = [
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "1n2k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "1n2k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1",
+            "1n2k1n1/8/8/8/8/8/8/4K3 b - - 0 1",
+            "1n2k3/8/8/8/8/8/8/1N2KN2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1NN1KN2 w - - 0 1",
+        ];
+
+        for fen in ongoing {
+            assert_eq!(state_after(fen, &[]), GameState::Ongoing, "{fen}");
+        }
+    }
+
+    #[test]
+    fn unmake_move_restores_position() {
+        let mut pos =
+            Chess::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+
+        // leave hashes on the stack for irreversible moves to clear
+        for mov in ["e1d1", "e8d8"] {
+            pos.make_move(pos.parse_move(mov).unwrap());
+        }
+
+        let before = pos.clone();
+        let mut moves = Vec::new();
+        pos.map_legal_moves(|mov| moves.push(mov));
+
+        for mov in moves {
+            let undo = pos.make_move(mov);
+
+            let mut replies = Vec::new();
+            pos.map_legal_moves(|reply| replies.push(reply));
+
+            for reply in replies {
+                let undo = pos.make_move(reply);
+                pos.unmake_move(undo);
             }
-        });
+
+            pos.unmake_move(undo);
+
+            assert_eq!(pos.as_fen(), before.as_fen());
+            assert_eq!(pos.stack.reversible(), before.stack.reversible());
+            assert!(pos.is_same(&before));
+        }
     }
 
-    count
+    #[test]
+    fn capture_into_insufficient_material() {
+        let fen = "4k3/8/8/8/8/8/3r4/3NK3 w - - 0 1";
+        assert_eq!(state_after(fen, &["e1d2"]), GameState::Draw);
+    }
 }
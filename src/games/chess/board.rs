@@ -1,4 +1,7 @@
-use crate::{games::GameState, pop_lsb};
+use crate::{
+    games::{check_counters, check_placement, FenError, GameState},
+    pop_lsb,
+};
 
 use super::{
     attacks::Attacks,
@@ -12,23 +15,30 @@ use super::{
 pub struct Board {
     bb: [u64; 8],
     hash: u64,
-    phase: i32,
     stm: bool,
     enp_sq: u8,
     rights: u8,
     halfm: u8,
+    fullm: u16,
 }
 
 impl Board {
-    pub fn from_raw(bb: [u64; 8], stm: bool, enp_sq: u8, rights: u8, halfm: u8) -> Self {
+    pub fn from_raw(
+        bb: [u64; 8],
+        stm: bool,
+        enp_sq: u8,
+        rights: u8,
+        halfm: u8,
+        fullm: u16,
+    ) -> Self {
         Self {
             bb,
             hash: 0,
-            phase: 0,
             stm,
             enp_sq,
             rights,
             halfm,
+            fullm,
         }
     }
 
@@ -63,6 +73,11 @@ impl Board {
         self.halfm
     }
 
+    #[must_use]
+    pub fn fullm(&self) -> u16 {
+        self.fullm
+    }
+
     #[must_use]
     pub fn hash(&self) -> u64 {
         let mut hash = self.hash;
@@ -106,19 +121,25 @@ impl Board {
         self.is_square_attacked(king as usize, self.stm(), self.occ())
     }
 
-    pub fn draw(&self) -> bool {
-        if self.halfm >= 100 {
-            return true;
+    /// Whether neither side has enough material to checkmate: bare kings,
+    /// a single minor piece, or bishops that all stand on one square colour.
+    /// Two knights against a bare king cannot force mate, but can still mate
+    /// with help, so that is not a dead position.
+    pub fn insufficient_material(&self) -> bool {
+        if self.bb[Piece::PAWN] | self.bb[Piece::ROOK] | self.bb[Piece::QUEEN] > 0 {
+            return false;
         }
 
-        let ph = self.phase;
-        let b = self.bb[Piece::BISHOP];
-        ph <= 2
-            && self.bb[Piece::PAWN] == 0
-            && ((ph != 2)
-                || (b & self.bb[Side::WHITE] != b
-                    && b & self.bb[Side::BLACK] != b
-                    && (b & 0x55AA55AA55AA55AA == b || b & 0xAA55AA55AA55AA55 == b)))
+        let knights = self.bb[Piece::KNIGHT];
+        let bishops = self.bb[Piece::BISHOP];
+
+        match (knights | bishops).count_ones() {
+            0 | 1 => true,
+            _ if knights == 0 => {
+                bishops & 0x55AA55AA55AA55AA == bishops || bishops & 0xAA55AA55AA55AA55 == bishops
+            }
+            _ => false,
+        }
     }
 
     fn repetition(&self, stack: &[u64]) -> bool {
@@ -140,18 +161,21 @@ impl Board {
     }
 
     pub fn game_state(&self, castling: &Castling, stack: &[u64]) -> GameState {
-        if self.draw() || self.repetition(stack) {
+        if self.insufficient_material() || self.repetition(stack) {
             return GameState::Draw;
         }
 
         let mut count = 0;
         self.map_legal_moves(castling, |_| count += 1);
 
+        // checkmate on the 100th ply takes precedence over the 50-move rule
         if count > 0 {
-            return GameState::Ongoing;
-        }
-
-        if self.in_check() {
+            if self.halfm >= 100 {
+                GameState::Draw
+            } else {
+                GameState::Ongoing
+            }
+        } else if self.in_check() {
             GameState::Lost(0)
         } else {
             GameState::Draw
@@ -159,60 +183,56 @@ impl Board {
     }
 
     pub fn map_value_features<F: FnMut(usize)>(&self, mut f: F) {
-        let flip = self.stm() == Side::BLACK;
-        let hm = if self.king_index() % 8 > 3 { 7 } else { 0 };
-
-        let mut threats = self.threats_by(self.stm() ^ 1);
-        let mut defences = self.threats_by(self.stm());
+        let view = ValueView::new(self);
 
-        if flip {
-            threats = threats.swap_bytes();
-            defences = defences.swap_bytes();
-        }
+        for (side, bbs) in view.pieces.iter().enumerate() {
+            for (piece, &bb) in bbs.iter().enumerate() {
+                let mut bb = bb;
 
-        for piece in Piece::PAWN..=Piece::KING {
-            let pc = 64 * (piece - 2);
-
-            let mut our_bb = self.piece(piece) & self.piece(self.stm());
-            let mut opp_bb = self.piece(piece) & self.piece(self.stm() ^ 1);
-
-            if flip {
-                our_bb = our_bb.swap_bytes();
-                opp_bb = opp_bb.swap_bytes();
+                while bb > 0 {
+                    pop_lsb!(sq, bb);
+                    f(view.feature(side, piece, sq));
+                }
             }
+        }
+    }
 
-            while our_bb > 0 {
-                pop_lsb!(sq, our_bb);
-                let mut feat = pc + usize::from(sq ^ hm);
+    /// Calls `f(feat, add)` for each feature gained or lost since `old`,
+    /// which must have the same side to move. Returns `false`, without
+    /// calling `f`, if the king has crossed to the other half of the board,
+    /// which changes every feature.
+    pub fn map_value_feature_deltas<F: FnMut(usize, bool)>(&self, old: &Self, mut f: F) -> bool {
+        let new = ValueView::new(self);
+        let old = ValueView::new(old);
 
-                let bit = 1 << sq;
-                if threats & bit > 0 {
-                    feat += 768;
-                }
+        if new.hm != old.hm {
+            return false;
+        }
 
-                if defences & bit > 0 {
-                    feat += 768 * 2;
-                }
+        // pieces that are now threatened or defended differently
+        let changed = (new.threats ^ old.threats) | (new.defences ^ old.defences);
 
-                f(feat);
-            }
+        for side in 0..2 {
+            for piece in 0..6 {
+                let new_bb = new.pieces[side][piece];
+                let old_bb = old.pieces[side][piece];
 
-            while opp_bb > 0 {
-                pop_lsb!(sq, opp_bb);
-                let mut feat = 384 + pc + usize::from(sq ^ hm);
+                let mut removed = old_bb & (!new_bb | changed);
+                let mut added = new_bb & (!old_bb | changed);
 
-                let bit = 1 << sq;
-                if threats & bit > 0 {
-                    feat += 768;
+                while removed > 0 {
+                    pop_lsb!(sq, removed);
+                    f(old.feature(side, piece, sq), false);
                 }
 
-                if defences & bit > 0 {
-                    feat += 768 * 2;
+                while added > 0 {
+                    pop_lsb!(sq, added);
+                    f(new.feature(side, piece, sq), true);
                 }
-
-                f(feat);
             }
         }
+
+        true
     }
 
     pub fn map_policy_features<F: FnMut(usize)>(&self, mut f: F) {
@@ -421,6 +441,7 @@ impl Board {
         };
 
         // updating state
+        self.fullm += u16::from(self.stm);
         self.stm = !self.stm;
         self.enp_sq = 0;
         self.rights &=
@@ -438,7 +459,6 @@ impl Board {
         // captures
         if captured != Piece::EMPTY {
             self.toggle(side ^ 1, captured, mov.to());
-            self.phase -= PHASE_VALS[captured];
         }
 
         // more complex moves
@@ -455,7 +475,6 @@ impl Board {
             Flag::ENP => self.toggle(side ^ 1, Piece::PAWN, mov.to() ^ 8),
             Flag::NPR.. => {
                 let promo = usize::from((mov.flag() & 3) + 3);
-                self.phase += PHASE_VALS[promo];
                 self.toggle(side, Piece::PAWN, mov.to());
                 self.toggle(side, promo, mov.to());
             }
@@ -487,7 +506,6 @@ impl Board {
                 let colour = usize::from(idx > 5);
                 let pc = idx + 2 - 6 * colour;
                 pos.toggle(colour, pc, (8 * row + col) as u16);
-                pos.phase += PHASE_VALS[pc];
                 col += 1;
             }
         }
@@ -506,9 +524,111 @@ impl Board {
             8 * chs[1].to_string().parse::<u8>().unwrap_or(0) + chs[0] as u8 - 105
         };
 
+        // halfmove clock
+        pos.halfm = vec.get(4).and_then(|hm| hm.parse().ok()).unwrap_or(0);
+
+        // fullmove counter
+        pos.fullm = vec.get(5).and_then(|fm| fm.parse().ok()).unwrap_or(1);
+
         pos
     }
 
+    pub fn try_parse_fen(fen: &str, castling: &mut Castling) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+
+        let field = |i, name| fields.get(i).copied().ok_or(FenError::MissingField(name));
+        let placement = field(0, "piece placement")?;
+        let stm = field(1, "side to move")?;
+        let rights = field(2, "castling rights")?;
+        let enp = field(3, "en passant square")?;
+
+        check_placement(placement, 8, "PNBRQKpnbrqk")?;
+
+        if stm != "w" && stm != "b" {
+            return Err(FenError::InvalidSideToMove(stm.to_string()));
+        }
+
+        let valid_right = |(i, ch): (usize, char)| {
+            "KQkqABCDEFGHabcdefgh".contains(ch) && !rights[..i].contains(ch)
+        };
+
+        if rights != "-" && !rights.char_indices().all(valid_right) {
+            return Err(FenError::InvalidCastling(rights.to_string()));
+        }
+
+        let enp_valid = enp == "-"
+            || matches!(enp.as_bytes(), [b'a'..=b'h', rank] if *rank == [b'6', b'3'][usize::from(stm == "b")]);
+
+        if !enp_valid {
+            return Err(FenError::InvalidEnPassant(enp.to_string()));
+        }
+
+        check_counters(&fields[4..])?;
+
+        let pos = Self::parse_fen(fen, castling);
+        pos.check_legality(castling, rights)?;
+
+        Ok(pos)
+    }
+
+    fn check_legality(&self, castling: &Castling, rights: &str) -> Result<(), FenError> {
+        for side in [Side::WHITE, Side::BLACK] {
+            let count = (self.bb[side] & self.bb[Piece::KING]).count_ones();
+            if count != 1 {
+                return Err(FenError::KingCount { side, count });
+            }
+        }
+
+        if self.bb[Piece::PAWN] & 0xFF000000000000FF > 0 {
+            return Err(FenError::PawnOnBackRank);
+        }
+
+        let nstm = self.stm() ^ 1;
+        if self.is_square_attacked(self.king_sq(nstm), nstm, self.occ()) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        for ch in rights.chars().filter(|&ch| ch != '-') {
+            let side = usize::from(ch.is_ascii_lowercase());
+            let king = self.king_sq(side);
+            let back_rank = 56 * side;
+
+            let kingside = match ch.to_ascii_uppercase() {
+                'K' => true,
+                'Q' => false,
+                file => usize::from(file as u8 - b'A') > king & 7,
+            };
+
+            let rook = back_rank + usize::from(castling.rook_file(side, usize::from(kingside)));
+            let rooks = self.bb[side] & self.bb[Piece::ROOK];
+
+            let king_placed =
+                king / 8 == side * 7 && (castling.is_chess960() || king == back_rank + 4);
+
+            if !king_placed || rooks & (1 << rook) == 0 || (rook > king) != kingside {
+                return Err(FenError::CastlingWithoutRook(ch));
+            }
+        }
+
+        if self.enp_sq > 0 {
+            let sq = usize::from(self.enp_sq);
+            let (pushed, from) = if self.stm() == Side::WHITE {
+                (sq - 8, sq + 8)
+            } else {
+                (sq + 8, sq - 8)
+            };
+
+            let pawns = self.bb[nstm] & self.bb[Piece::PAWN];
+
+            if pawns & (1 << pushed) == 0 || self.occ() & ((1 << sq) | (1 << from)) > 0 {
+                let file = char::from(b'a' + (sq & 7) as u8);
+                return Err(FenError::InvalidEnPassant(format!("{file}{}", sq / 8 + 1)));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn map_legal_moves<F: FnMut(Move)>(&self, castling: &Castling, mut f: F) {
         self.map_legal_moves_internal::<true, F>(castling, &mut f);
     }
@@ -828,7 +948,8 @@ impl Board {
         }
     }
 
-    pub fn as_fen(&self) -> String {
+    /// Castling rights are written as rook files in chess960.
+    pub fn as_fen(&self, castling: &Castling) -> String {
         const PIECES: [char; 12] = ['P', 'N', 'B', 'R', 'Q', 'K', 'p', 'n', 'b', 'r', 'q', 'k'];
         let mut fen = String::new();
 
@@ -866,15 +987,36 @@ impl Board {
         if self.rights == 0 {
             fen.push('-');
         } else {
-            let mut r = self.rights;
-            while r > 0 {
-                let q = r.trailing_zeros();
-                r &= r - 1;
-                fen.push(['k', 'q', 'K', 'Q'][q as usize]);
+            let rights = [Right::WKS, Right::WQS, Right::BKS, Right::BQS];
+
+            for (i, right) in rights.into_iter().enumerate() {
+                if self.rights & right == 0 {
+                    continue;
+                }
+
+                let (side, ks) = (i / 2, usize::from(i % 2 == 0));
+                let ch = if castling.is_chess960() {
+                    let file = castling.rook_file(side, ks) as u8;
+                    char::from([b'A', b'a'][side] + file)
+                } else {
+                    ['K', 'Q', 'k', 'q'][i]
+                };
+
+                fen.push(ch);
             }
         }
 
-        fen.push_str(" - 0 1");
+        fen.push(' ');
+
+        if self.enp_sq > 0 {
+            let sq = self.enp_sq;
+            fen.push(char::from(b'a' + (sq & 7)));
+            fen.push(char::from(b'1' + sq / 8));
+        } else {
+            fen.push('-');
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfm, self.fullm));
 
         fen
     }
@@ -938,3 +1080,49 @@ fn btwn(bit1: u64, bit2: u64) -> u64 {
     let min = bit1.min(bit2);
     (bit1.max(bit2) - min) ^ min
 }
+
+/// Everything the value features of a position depend on,
+/// from the perspective of the side to move.
+struct ValueView {
+    pieces: [[u64; 6]; 2],
+    threats: u64,
+    defences: u64,
+    hm: u16,
+}
+
+impl ValueView {
+    fn new(board: &Board) -> Self {
+        let flip = board.stm() == Side::BLACK;
+        let orient = |bb: u64| if flip { bb.swap_bytes() } else { bb };
+
+        let mut pieces = [[0; 6]; 2];
+
+        for piece in Piece::PAWN..=Piece::KING {
+            let bb = board.piece(piece);
+            pieces[0][piece - 2] = orient(bb & board.piece(board.stm()));
+            pieces[1][piece - 2] = orient(bb & board.piece(board.stm() ^ 1));
+        }
+
+        Self {
+            pieces,
+            threats: orient(board.threats_by(board.stm() ^ 1)),
+            defences: orient(board.threats_by(board.stm())),
+            hm: if board.king_index() % 8 > 3 { 7 } else { 0 },
+        }
+    }
+
+    fn feature(&self, side: usize, piece: usize, sq: u16) -> usize {
+        let mut feat = 384 * side + 64 * piece + usize::from(sq ^ self.hm);
+
+        let bit = 1 << sq;
+        if self.threats & bit > 0 {
+            feat += 768;
+        }
+
+        if self.defences & bit > 0 {
+            feat += 768 * 2;
+        }
+
+        feat
+    }
+}
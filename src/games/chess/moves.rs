@@ -62,7 +62,17 @@ impl Move {
         }
     }
 
+    pub fn is_castle(&self) -> bool {
+        [Flag::QS, Flag::KS].contains(&self.flag())
+    }
+
     pub fn to_uci(self, castling: &Castling) -> String {
+        self.to_uci_with(castling, castling.is_chess960())
+    }
+
+    /// Castling is written as the king capturing its own rook if
+    /// `king_takes_rook`, otherwise as the king's destination.
+    pub fn to_uci_with(self, castling: &Castling, king_takes_rook: bool) -> String {
         let idx_to_sq = |i| format!("{}{}", ((i & 7) as u8 + b'a') as char, (i / 8) + 1);
         let promo = if self.flag() & 0b1000 > 0 {
             ["n", "b", "r", "q"][(self.flag() & 0b11) as usize]
@@ -70,7 +80,7 @@ impl Move {
             ""
         };
 
-        let to = if castling.is_chess960() && [Flag::QS, Flag::KS].contains(&self.flag()) {
+        let to = if king_takes_rook && self.is_castle() {
             let sf = 56 * (self.to() / 56);
             sf + castling.rook_file(usize::from(sf > 0), usize::from(self.flag() == Flag::KS))
         } else {
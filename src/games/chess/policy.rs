@@ -1,4 +1,4 @@
-use super::{moves::Move, Board};
+use super::{moves::Move, Board, Castling};
 
 use goober::{activation, layer, FeedForwardNetwork, Matrix, SparseVector, Vector};
 
@@ -72,3 +72,226 @@ impl PolicyNetwork {
         feats
     }
 }
+
+const QA: i32 = 512;
+const QB: i32 = 512;
+const QAB: i32 = QA * QB;
+
+const HIDDEN: usize = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct QuantisedSubNet {
+    ft_weights: [[i16; HIDDEN]; 768],
+    ft_bias: [i16; HIDDEN],
+    // output-major, matching goober's `DenseConnected`
+    l2_weights: [[i16; HIDDEN]; HIDDEN],
+    l2_bias: [i32; HIDDEN],
+}
+
+impl QuantisedSubNet {
+    fn from_unquantised(subnet: &SubNet) -> Self {
+        let quantise = |x: f32, q: i32| (x * q as f32).round().clamp(-32768.0, 32767.0) as i16;
+
+        let mut ret = Self {
+            ft_weights: [[0; HIDDEN]; 768],
+            ft_bias: [0; HIDDEN],
+            l2_weights: [[0; HIDDEN]; HIDDEN],
+            l2_bias: [0; HIDDEN],
+        };
+
+        for (i, row) in ret.ft_weights.iter_mut().enumerate() {
+            let weights = subnet.ft.weights_row(i);
+
+            for (j, w) in row.iter_mut().enumerate() {
+                *w = quantise(weights[j], QA);
+            }
+        }
+
+        let ft_bias = subnet.ft.bias();
+        for (i, b) in ret.ft_bias.iter_mut().enumerate() {
+            *b = quantise(ft_bias[i], QA);
+        }
+
+        for (i, row) in ret.l2_weights.iter_mut().enumerate() {
+            let weights = subnet.l2.weights_row(i);
+
+            for (j, w) in row.iter_mut().enumerate() {
+                *w = quantise(weights[j], QB);
+            }
+        }
+
+        let l2_bias = subnet.l2.bias();
+        for (i, b) in ret.l2_bias.iter_mut().enumerate() {
+            *b = (l2_bias[i] * QAB as f32).round() as i32;
+        }
+
+        ret
+    }
+
+    fn out(&self, feats: &[u16]) -> [f32; HIDDEN] {
+        let mut ft = self.ft_bias.map(i32::from);
+
+        for &feat in feats {
+            for (i, &w) in ft.iter_mut().zip(self.ft_weights[usize::from(feat)].iter()) {
+                *i += i32::from(w);
+            }
+        }
+
+        let ft = ft.map(|x| x.max(0));
+        let mut l2 = self.l2_bias;
+
+        for (i, row) in l2.iter_mut().zip(self.l2_weights.iter()) {
+            for (&x, &w) in ft.iter().zip(row.iter()) {
+                *i += x * i32::from(w);
+            }
+        }
+
+        l2.map(|x| x.max(0) as f32 / QAB as f32)
+    }
+}
+
+/// Inputs to the policy network shared by every move of a position.
+pub struct PolicyFeats {
+    feats: Vec<u16>,
+    threats: u64,
+}
+
+/// The policy network used in search, quantised from a `PolicyNetwork`
+/// when the engine starts, which stays the reference for validation.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct QuantisedPolicyNetwork {
+    pub subnets: [[QuantisedSubNet; 2]; 128],
+    pub hce: layer::DenseConnected<activation::Identity, 4, 1>,
+}
+
+impl QuantisedPolicyNetwork {
+    pub fn from_unquantised(policy: &PolicyNetwork) -> Box<Self> {
+        let mut quantised: Box<Self> = unsafe { crate::boxed_and_zeroed() };
+
+        for (qpair, pair) in quantised.subnets.iter_mut().zip(policy.subnets.iter()) {
+            for (qsubnet, subnet) in qpair.iter_mut().zip(pair.iter()) {
+                *qsubnet = QuantisedSubNet::from_unquantised(subnet);
+            }
+        }
+
+        quantised.hce = policy.hce;
+
+        quantised
+    }
+
+    fn subnets(pos: &Board, mov: &Move, threats: u64) -> (usize, usize) {
+        let flip = pos.flip_val();
+
+        let from_threat = usize::from(threats & (1 << mov.from()) > 0);
+        let from = 2 * usize::from(mov.from() ^ flip) + from_threat;
+
+        let good_see = usize::from(pos.see(mov, -108));
+        let to = 2 * (64 + usize::from(mov.to() ^ flip)) + good_see;
+
+        (from, to)
+    }
+
+    pub fn feats(pos: &Board) -> PolicyFeats {
+        let mut feats = Vec::with_capacity(32);
+        pos.map_policy_features(|feat| feats.push(feat as u16));
+
+        PolicyFeats {
+            feats,
+            threats: pos.threats(),
+        }
+    }
+
+    fn subnet_out(&self, subnet: usize, feats: &PolicyFeats) -> [f32; HIDDEN] {
+        self.subnets[subnet / 2][subnet % 2].out(&feats.feats)
+    }
+
+    fn policy(&self, pos: &Board, mov: &Move, from: &[f32; HIDDEN], to: &[f32; HIDDEN]) -> f32 {
+        let dot = from.iter().zip(to.iter()).map(|(i, j)| i * j).sum::<f32>();
+        let hce = self.hce.out(&PolicyNetwork::get_hce_feats(pos, mov))[0];
+
+        dot + hce
+    }
+
+    /// Policy of a single move, which has to run both of its subnets.
+    pub fn get(&self, pos: &Board, mov: &Move, feats: &PolicyFeats) -> f32 {
+        let (from, to) = Self::subnets(pos, mov, feats.threats);
+        let from = self.subnet_out(from, feats);
+        let to = self.subnet_out(to, feats);
+
+        self.policy(pos, mov, &from, &to)
+    }
+
+    /// Calls `f` with every legal move of `pos` and its policy, running
+    /// each subnet once per position rather than once per move.
+    pub fn map_policies<F: FnMut(Move, f32)>(&self, pos: &Board, castling: &Castling, mut f: F) {
+        let feats = Self::feats(pos);
+        let mut idx = [u16::MAX; 256];
+        let mut outs = Vec::new();
+
+        pos.map_legal_moves(castling, |mov| {
+            let (from, to) = Self::subnets(pos, &mov, feats.threats);
+
+            for subnet in [from, to] {
+                if idx[subnet] == u16::MAX {
+                    idx[subnet] = outs.len() as u16;
+                    outs.push(self.subnet_out(subnet, &feats));
+                }
+            }
+
+            let from = &outs[usize::from(idx[from])];
+            let to = &outs[usize::from(idx[to])];
+
+            f(mov, self.policy(pos, &mov, from, to));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chess::Uci, UciLike};
+
+    #[test]
+    fn quantised_matches_unquantised() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % 1024) as f32 / 1024.0 - 0.5
+        };
+
+        let mut policy: Box<PolicyNetwork> = unsafe { crate::boxed_and_zeroed() };
+
+        for pair in policy.subnets.iter_mut() {
+            for subnet in pair.iter_mut() {
+                *subnet = SubNet::from_fn(&mut rand);
+            }
+        }
+
+        let quantised = QuantisedPolicyNetwork::from_unquantised(&policy);
+
+        for fen in Uci::FEN_STRING.lines().take(8) {
+            let mut castling = Castling::default();
+            let board = Board::parse_fen(fen, &mut castling);
+
+            let mut feats = SparseVector::with_capacity(32);
+            board.map_policy_features(|feat| feats.push(feat));
+            let threats = board.threats();
+
+            let single = QuantisedPolicyNetwork::feats(&board);
+
+            quantised.map_policies(&board, &castling, |mov, quant| {
+                let float = policy.get(&board, &mov, &feats, threats);
+                assert!(
+                    (float - quant).abs() < 0.02 + 0.02 * float.abs(),
+                    "{fen}: {float} vs {quant}"
+                );
+
+                assert_eq!(quantised.get(&board, &mov, &single), quant);
+            });
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::games::san::{disambiguation, square_name, San};
+
+use super::{
+    board::Board,
+    consts::{Flag, Piece},
+    frc::Castling,
+    moves::Move,
+};
+
+const PIECES: [char; 6] = ['P', 'N', 'B', 'R', 'Q', 'K'];
+
+impl Board {
+    /// Castling is written as `O-O` or `O-O-O` in chess960 too.
+    pub fn to_san(&self, mov: Move, castling: &Castling) -> String {
+        let mut san = if mov.is_castle() {
+            String::from(if mov.flag() == Flag::KS {
+                "O-O"
+            } else {
+                "O-O-O"
+            })
+        } else {
+            let from = usize::from(mov.from());
+            let to = usize::from(mov.to());
+            let pc = self.get_pc(1 << from);
+
+            let mut san = String::new();
+
+            if pc == Piece::PAWN {
+                if mov.is_capture() {
+                    san.push_str(&square_name(from)[..1]);
+                }
+            } else {
+                let mut others = Vec::new();
+
+                self.map_legal_moves(castling, |other| {
+                    let other_from = usize::from(other.from());
+
+                    if other_from != from
+                        && usize::from(other.to()) == to
+                        && !other.is_castle()
+                        && self.get_pc(1 << other_from) == pc
+                    {
+                        others.push(other_from);
+                    }
+                });
+
+                san.push(PIECES[pc - Piece::PAWN]);
+                san.push_str(&disambiguation(from, &others));
+            }
+
+            if mov.is_capture() {
+                san.push('x');
+            }
+
+            san.push_str(&square_name(to));
+
+            if mov.is_promo() {
+                san.push('=');
+                san.push(PIECES[mov.promo_pc() - Piece::PAWN]);
+            }
+
+            san
+        };
+
+        let mut next = *self;
+        next.make(mov, castling);
+
+        if next.in_check() {
+            let mut mated = true;
+            next.map_legal_moves(castling, |_| mated = false);
+            san.push(if mated { '#' } else { '+' });
+        }
+
+        san
+    }
+
+    pub fn parse_san(&self, san: &str, castling: &Castling) -> Option<Move> {
+        let san = San::parse(san)?;
+
+        let mut found = None;
+        let mut count = 0;
+
+        self.map_legal_moves(castling, |mov| {
+            let is_match = match san.castle {
+                Some(kingside) => mov.flag() == [Flag::QS, Flag::KS][usize::from(kingside)],
+                None => {
+                    let from = usize::from(mov.from());
+                    let pc = PIECES[self.get_pc(1 << from) - Piece::PAWN];
+                    let promo = mov.is_promo().then(|| PIECES[mov.promo_pc() - Piece::PAWN]);
+
+                    !mov.is_castle()
+                        && promo == san.promo
+                        && san.matches(pc, from, usize::from(mov.to()))
+                }
+            };
+
+            if is_match {
+                found = Some(mov);
+                count += 1;
+            }
+        });
+
+        if count == 1 {
+            found
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        comm::UciLike,
+        games::{
+            chess::{Chess, Uci},
+            GameRep,
+        },
+    };
+
+    fn san(fen: &str, mov: &str) -> String {
+        let pos = Chess::from_fen(fen);
+        pos.conv_mov_to_san(pos.parse_move(mov).unwrap())
+    }
+
+    #[test]
+    fn disambiguation() {
+        let knights = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san(knights, "b1d2"), "Nbd2");
+        assert_eq!(san(knights, "f3d2"), "Nfd2");
+        assert_eq!(san(knights, "b1c3"), "Nc3");
+
+        let rooks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(rooks, "a1a3"), "R1a3");
+        assert_eq!(san(rooks, "a5a3"), "R5a3");
+
+        let queens = "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1";
+        assert_eq!(san(queens, "a1b2"), "Qa1b2");
+        assert_eq!(san(queens, "a3b2"), "Q3b2");
+        assert_eq!(san(queens, "c1b2"), "Qcb2");
+    }
+
+    #[test]
+    fn checks() {
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn castling() {
+        let pos = Chess::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+
+        for (san, alt) in [("O-O", "0-0"), ("O-O-O", "0-0-0")] {
+            let mov = pos.parse_san(san).unwrap();
+            assert_eq!(pos.conv_mov_to_san(mov), san);
+            assert_eq!(pos.parse_san(alt).map(u16::from), Some(mov.into()));
+        }
+    }
+
+    #[test]
+    fn promotions() {
+        let fen = "r7/1P2k3/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "b7b8q"), "b8=Q");
+        assert_eq!(san(fen, "b7b8n"), "b8=N");
+        assert_eq!(san(fen, "b7a8r"), "bxa8=R");
+
+        let pos = Chess::from_fen(fen);
+        assert_eq!(
+            pos.parse_san("b8Q").map(u16::from),
+            pos.parse_move("b7b8q").map(u16::from)
+        );
+        assert_eq!(
+            pos.parse_san("bxa8=n").map(u16::from),
+            pos.parse_move("b7a8n").map(u16::from)
+        );
+        assert_eq!(pos.parse_san("b8").map(u16::from), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let frc = include_str!("../../../resources/frc-perft.txt");
+        let fens = Uci::FEN_STRING.lines().chain(
+            frc.lines()
+                .map(|line| line.split(';').next().unwrap().trim()),
+        );
+
+        for fen in fens {
+            let mut pos = Chess::from_fen(fen);
+            let mut moves = Vec::new();
+            pos.map_legal_moves(|mov| moves.push(mov));
+
+            for mov in moves {
+                let undo = pos.make_move(mov);
+
+                pos.map_legal_moves(|reply| {
+                    let san = pos.conv_mov_to_san(reply);
+                    assert_eq!(
+                        pos.parse_san(&san).map(u16::from),
+                        Some(reply.into()),
+                        "{fen} {san}"
+                    );
+                });
+
+                pos.unmake_move(undo);
+
+                let san = pos.conv_mov_to_san(mov);
+                assert_eq!(
+                    pos.parse_san(&san).map(u16::from),
+                    Some(mov.into()),
+                    "{fen} {san}"
+                );
+            }
+        }
+    }
+}
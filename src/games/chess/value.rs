@@ -1,7 +1,13 @@
+use crate::value::ValueFeatureMap;
+
 use super::Board;
 
 const SCALE: i32 = 400;
 
+// incremental updates in a row before refreshing,
+// so that floating point error can't build up
+const REFRESH_INTERVAL: u8 = 16;
+
 #[repr(C)]
 pub struct ValueNetwork {
     l1: Layer<{ 768 * 4 }, 512>,
@@ -17,17 +23,70 @@ pub struct ValueNetwork {
     l11: Layer<16, 1>,
 }
 
+/// Hidden layer of the first layer of a `ValueNetwork`, along with the
+/// position it was produced for and the updates made since a refresh.
+#[derive(Clone, Copy)]
+pub struct ValueAccumulator {
+    l2: Accumulator<512>,
+    board: Option<Board>,
+    updates: u8,
+}
+
+impl Default for ValueAccumulator {
+    fn default() -> Self {
+        Self {
+            l2: Accumulator { vals: [0.0; 512] },
+            board: None,
+            updates: 0,
+        }
+    }
+}
+
 impl ValueNetwork {
+    pub const SCALE: i32 = SCALE;
+
     pub fn eval(&self, board: &Board) -> i32 {
-        let mut l2 = self.l1.biases;
+        let mut acc = ValueAccumulator::default();
+        self.refresh(board, &mut acc);
+        self.eval_accumulator(&acc)
+    }
+
+    pub fn refresh(&self, board: &Board, acc: &mut ValueAccumulator) {
+        acc.l2 = self.l1.biases;
+        acc.board = Some(*board);
+        acc.updates = 0;
 
-        board.map_value_features(|feat| {
-            for (i, d) in l2.vals.iter_mut().zip(&self.l1.weights[feat].vals) {
-                *i += *d;
+        board.value_feature_map(|feat| acc.l2.madd(1.0, &self.l1.weights[feat]));
+    }
+
+    /// Updates `acc` to be the accumulator for `board`, starting from `base`,
+    /// by applying only the features changed by the moves in between.
+    pub fn update(&self, board: &Board, base: &ValueAccumulator, acc: &mut ValueAccumulator) {
+        let old = match base.board {
+            Some(old) if base.updates < REFRESH_INTERVAL => old,
+            _ => {
+                self.refresh(board, acc);
+                return;
             }
+        };
+
+        acc.l2 = base.l2;
+
+        let updated = board.value_feature_deltas(&old, |feat, add| {
+            let mul = if add { 1.0 } else { -1.0 };
+            acc.l2.madd(mul, &self.l1.weights[feat]);
         });
 
-        let l3 = self.l2.forward(&l2);
+        if updated {
+            acc.board = Some(*board);
+            acc.updates = base.updates + 1;
+        } else {
+            self.refresh(board, acc);
+        }
+    }
+
+    pub fn eval_accumulator(&self, acc: &ValueAccumulator) -> i32 {
+        let l3 = self.l2.forward(&acc.l2);
         let l4 = self.l3.forward(&l3);
         let l5 = self.l4.forward(&l4);
         let l6 = self.l5.forward(&l5);
@@ -0,0 +1,201 @@
+use super::GameRep;
+
+/// The result of a perft search. `checksum` is the wrapping
+/// sum of the hashes of every leaf position, if requested.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PerftResult {
+    pub nodes: u64,
+    pub checksum: u64,
+}
+
+impl std::ops::AddAssign for PerftResult {
+    fn add_assign(&mut self, rhs: Self) {
+        self.nodes += rhs.nodes;
+        self.checksum = self.checksum.wrapping_add(rhs.checksum);
+    }
+}
+
+/// Counts the positions `depth` moves from `pos`. With `bulk`, moves
+/// on the last ply are counted without being made, unless `hash` is
+/// set, as every leaf position has to be reached to be hashed.
+pub fn perft<T: GameRep>(pos: &mut T, depth: usize, bulk: bool, hash: bool) -> PerftResult {
+    let mut buffers = move_buffers::<T>(depth);
+    perft_with(pos, depth, bulk, hash, &mut buffers)
+}
+
+/// Runs perft separately for each legal move from `pos`.
+pub fn divide<T: GameRep>(
+    pos: &mut T,
+    depth: usize,
+    bulk: bool,
+    hash: bool,
+) -> Vec<(T::Move, PerftResult)> {
+    let mut moves = Vec::new();
+    pos.map_legal_moves(|mov| moves.push(mov));
+
+    let depth = depth.saturating_sub(1);
+    let mut buffers = move_buffers::<T>(depth);
+
+    moves
+        .into_iter()
+        .map(|mov| {
+            let undo = pos.make_move_untracked(mov);
+            let result = perft_with(pos, depth, bulk, hash, &mut buffers);
+            pos.unmake_move(undo);
+
+            (mov, result)
+        })
+        .collect()
+}
+
+// one list of moves for each ply, so that none are allocated during the search
+fn move_buffers<T: GameRep>(depth: usize) -> Vec<Vec<T::Move>> {
+    (0..depth)
+        .map(|_| Vec::with_capacity(T::MAX_MOVES))
+        .collect()
+}
+
+fn perft_with<T: GameRep>(
+    pos: &mut T,
+    depth: usize,
+    bulk: bool,
+    hash: bool,
+    buffers: &mut [Vec<T::Move>],
+) -> PerftResult {
+    if depth == 0 {
+        let checksum = if hash { pos.hash() } else { 0 };
+        return PerftResult { nodes: 1, checksum };
+    }
+
+    if bulk && !hash && depth == 1 {
+        let mut nodes = 0;
+        pos.map_legal_moves(|_| nodes += 1);
+        return PerftResult { nodes, checksum: 0 };
+    }
+
+    let (moves, rest) = buffers.split_first_mut().unwrap();
+    moves.clear();
+    pos.map_legal_moves(|mov| moves.push(mov));
+
+    let mut result = PerftResult::default();
+
+    for &mov in moves.iter() {
+        let undo = pos.make_move_untracked(mov);
+        result += perft_with(pos, depth - 1, bulk, hash, rest);
+        pos.unmake_move(undo);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::{ataxx::Ataxx, chess::Chess, shatranj::Shatranj};
+
+    // deeper entries are too slow for unoptimised builds, so are only
+    // checked by `full_suites`: `cargo test --release -- --ignored`
+    const MAX_NODES: u64 = 1_000_000;
+
+    const CHESS: &str = include_str!("../../resources/chess-perft.txt");
+    const FRC: &str = include_str!("../../resources/frc-perft.txt");
+    const SHATRANJ: &str = include_str!("../../resources/shatranj-perft.txt");
+    const ATAXX: &str = include_str!("../../resources/ataxx-perft.txt");
+
+    /// Checks every entry of a suite with at most `max_nodes` nodes,
+    /// given in the form `<fen> ;D1 <nodes> ;D2 <nodes> ...`.
+    fn run_suite<T: GameRep>(suite: &str, max_nodes: u64) {
+        for line in suite.lines() {
+            let mut fields = line.split(';');
+            let fen = fields.next().unwrap().trim();
+            let mut pos = T::try_from_fen(fen).unwrap();
+
+            for field in fields {
+                let (depth, nodes) = field.trim().split_once(' ').unwrap();
+                let depth = depth.trim_start_matches('D').parse().unwrap();
+                let nodes = nodes.parse().unwrap();
+
+                if nodes <= max_nodes {
+                    let result = perft(&mut pos, depth, true, false);
+                    assert_eq!(result.nodes, nodes, "{fen} depth {depth}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chess() {
+        run_suite::<Chess>(CHESS, MAX_NODES);
+    }
+
+    #[test]
+    fn frc() {
+        run_suite::<Chess>(FRC, MAX_NODES);
+    }
+
+    #[test]
+    fn shatranj() {
+        run_suite::<Shatranj>(SHATRANJ, MAX_NODES);
+    }
+
+    #[test]
+    fn ataxx() {
+        run_suite::<Ataxx>(ATAXX, MAX_NODES);
+    }
+
+    #[test]
+    #[ignore]
+    fn full_suites() {
+        run_suite::<Chess>(CHESS, u64::MAX);
+        run_suite::<Chess>(FRC, u64::MAX);
+        run_suite::<Shatranj>(SHATRANJ, u64::MAX);
+        run_suite::<Ataxx>(ATAXX, u64::MAX);
+    }
+
+    fn check_checksum<T: GameRep>(fen: &str, depth: usize, nodes: u64, checksum: u64) {
+        let mut pos = T::try_from_fen(fen).unwrap();
+        let result = perft(&mut pos, depth, true, true);
+        assert_eq!(
+            result,
+            PerftResult { nodes, checksum },
+            "{fen} depth {depth}"
+        );
+    }
+
+    // reference sums of the leaf hashes, which change along with the
+    // hash keys of a game
+    #[test]
+    fn checksums() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let endgame = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+
+        check_checksum::<Chess>(Chess::STARTPOS, 4, 197281, 0xe92f_fc25_d153_44d8);
+        check_checksum::<Chess>(kiwipete, 3, 97862, 0xe251_3ba3_81ee_9307);
+        check_checksum::<Chess>(endgame, 4, 43238, 0xfb60_affd_346a_1272);
+        check_checksum::<Shatranj>(Shatranj::STARTPOS, 4, 68122, 0x0270_d99b_2f57_bd69);
+        check_checksum::<Ataxx>(Ataxx::STARTPOS, 4, 155888, 0x2337_cfc3_9d31_89b9);
+    }
+
+    #[test]
+    fn modes_agree() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut pos = Chess::from_fen(fen);
+
+        let bulk = perft(&mut pos, 3, true, false);
+        let full = perft(&mut pos, 3, false, false);
+        let hashed = perft(&mut pos, 3, true, true);
+
+        assert_eq!(bulk.nodes, 97862);
+        assert_eq!(full.nodes, bulk.nodes);
+        assert_eq!(hashed.nodes, bulk.nodes);
+        assert_ne!(hashed.checksum, 0);
+
+        let mut total = PerftResult::default();
+        for (_, result) in divide(&mut pos, 3, false, true) {
+            total += result;
+        }
+
+        assert_eq!(total, hashed);
+        assert!(pos.is_same(&Chess::from_fen(fen)));
+    }
+}
@@ -0,0 +1,187 @@
+/// A move in standard algebraic notation, split into its parts
+/// but not yet matched against the legal moves of a position.
+pub(crate) struct San {
+    /// Upper case piece letter, `P` for pawns. The shatranj
+    /// letters `F` and `A` are accepted for the ferz and alfil.
+    pub piece: char,
+    /// File and rank of the moving piece, if given to disambiguate.
+    pub file: Option<u8>,
+    pub rank: Option<u8>,
+    pub to: u8,
+    pub promo: Option<char>,
+    /// Whether castling is kingside, if the move is a castle.
+    pub castle: Option<bool>,
+}
+
+impl San {
+    /// Accepts the variations found in the wild, such as missing
+    /// capture signs, `0-0` for castling and promotions without `=`.
+    pub fn parse(san: &str) -> Option<Self> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+
+        let castle = match san {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+
+        if castle.is_some() {
+            return Some(Self {
+                piece: 'K',
+                file: None,
+                rank: None,
+                to: 0,
+                promo: None,
+                castle,
+            });
+        }
+
+        let (piece, rest) = match san.chars().next()? {
+            ch @ ('N' | 'B' | 'R' | 'Q' | 'K' | 'F' | 'A') => (ch, &san[1..]),
+            'a'..='h' => ('P', san),
+            _ => return None,
+        };
+
+        let (rest, promo) = if let Some((rest, promo)) = rest.split_once('=') {
+            (rest, Some(promo))
+        } else if piece == 'P' && rest.ends_with(|ch: char| ch.is_ascii_alphabetic()) {
+            let (rest, promo) = rest.split_at(rest.len() - 1);
+            (rest, Some(promo))
+        } else {
+            (rest, None)
+        };
+
+        let promo = match promo.map(|promo| promo.chars().collect::<Vec<_>>()) {
+            Some(chars) if chars.len() == 1 => Some(chars[0].to_ascii_uppercase()),
+            Some(_) => return None,
+            None => None,
+        };
+
+        let rest = rest
+            .chars()
+            .filter(|&ch| !matches!(ch, 'x' | ':' | '-'))
+            .collect::<Vec<_>>();
+
+        if rest.len() < 2 {
+            return None;
+        }
+
+        let (disambiguation, to) = rest.split_at(rest.len() - 2);
+        let to = match to {
+            [file @ 'a'..='h', rank @ '1'..='8'] => 8 * (*rank as u8 - b'1') + (*file as u8 - b'a'),
+            _ => return None,
+        };
+
+        let mut file = None;
+        let mut rank = None;
+
+        for &ch in disambiguation {
+            match ch {
+                'a'..='h' if file.is_none() => file = Some(ch as u8 - b'a'),
+                '1'..='8' if rank.is_none() => rank = Some(ch as u8 - b'1'),
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            piece,
+            file,
+            rank,
+            to,
+            promo,
+            castle: None,
+        })
+    }
+
+    /// Whether a move of `piece` from `from` to `to` fits this notation.
+    pub fn matches(&self, piece: char, from: usize, to: usize) -> bool {
+        self.piece == piece
+            && usize::from(self.to) == to
+            && self.file.is_none_or(|file| usize::from(file) == from & 7)
+            && self.rank.is_none_or(|rank| usize::from(rank) == from / 8)
+    }
+}
+
+pub(crate) fn square_name(sq: usize) -> String {
+    format!("{}{}", char::from(b'a' + (sq & 7) as u8), sq / 8 + 1)
+}
+
+/// What must be added to a piece's SAN to tell it apart from the
+/// other pieces of the same type that can move to the same square.
+pub(crate) fn disambiguation(from: usize, others: &[usize]) -> String {
+    let file = char::from(b'a' + (from & 7) as u8);
+    let rank = char::from(b'1' + (from / 8) as u8);
+
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|sq| sq & 7 != from & 7) {
+        file.to_string()
+    } else if others.iter().all(|sq| sq / 8 != from / 8) {
+        rank.to_string()
+    } else {
+        format!("{file}{rank}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(san: &str) -> (char, Option<u8>, Option<u8>, u8, Option<char>, Option<bool>) {
+        let san = San::parse(san).unwrap();
+        (san.piece, san.file, san.rank, san.to, san.promo, san.castle)
+    }
+
+    #[test]
+    fn parts() {
+        assert_eq!(parse("e4"), ('P', None, None, 28, None, None));
+        assert_eq!(parse("Nbd2"), ('N', Some(1), None, 11, None, None));
+        assert_eq!(parse("R1a3"), ('R', None, Some(0), 16, None, None));
+        assert_eq!(parse("Qa1xb2"), ('Q', Some(0), Some(0), 9, None, None));
+        assert_eq!(parse("exd5"), ('P', Some(4), None, 35, None, None));
+    }
+
+    #[test]
+    fn suffixes() {
+        for san in ["Ra8+", "Ra8#", "Ra8!", "Ra8?!", "Ra8+!!"] {
+            assert_eq!(parse(san), ('R', None, None, 56, None, None), "{san}");
+        }
+    }
+
+    #[test]
+    fn castling() {
+        for (san, kingside) in [
+            ("O-O", true),
+            ("0-0", true),
+            ("O-O-O", false),
+            ("0-0-0#", false),
+        ] {
+            assert_eq!(parse(san).5, Some(kingside), "{san}");
+        }
+    }
+
+    #[test]
+    fn promotions() {
+        for san in ["b8=Q", "b8Q", "b8=q", "b8=Q+"] {
+            assert_eq!(parse(san), ('P', None, None, 57, Some('Q'), None), "{san}");
+        }
+
+        assert_eq!(parse("bxa8=N"), ('P', Some(1), None, 56, Some('N'), None));
+        assert!(San::parse("b8=QQ").is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        for san in ["", "Z4", "Ni9", "e", "Nbbd2", "O-O-O-O"] {
+            assert!(San::parse(san).is_none(), "{san}");
+        }
+    }
+
+    #[test]
+    fn disambiguate() {
+        assert_eq!(disambiguation(1, &[]), "");
+        assert_eq!(disambiguation(1, &[21]), "b");
+        assert_eq!(disambiguation(0, &[32]), "1");
+        assert_eq!(disambiguation(0, &[16, 2]), "a1");
+    }
+}
@@ -3,6 +3,7 @@ mod board;
 mod consts;
 mod moves;
 mod policy;
+mod san;
 
 pub use board::Board;
 pub use moves::Move;
@@ -10,8 +11,8 @@ pub use policy::{PolicyNetwork, SubNet};
 
 use crate::{
     comm::UciLike,
-    games::{GameRep, GameState},
-    value::{ValueFeatureMap, ValueNetwork},
+    games::{FenError, GameRep, GameState, HashStack, Undo},
+    value::{ValueAccumulator, ValueFeatureMap, ValueNetwork},
     MctsParams,
 };
 
@@ -21,6 +22,11 @@ impl ValueFeatureMap for Board {
     fn value_feature_map<F: FnMut(usize)>(&self, f: F) {
         self.features_map(f);
     }
+
+    fn value_feature_deltas<F: FnMut(usize, bool)>(&self, old: &Self, f: F) -> bool {
+        self.feature_deltas(old, f);
+        true
+    }
 }
 
 pub struct Uci;
@@ -40,7 +46,7 @@ impl UciLike for Uci {
 #[derive(Clone)]
 pub struct Shatranj {
     board: Board,
-    stack: Vec<u64>,
+    stack: HashStack,
 }
 
 impl Default for Shatranj {
@@ -49,7 +55,7 @@ impl Default for Shatranj {
 
         Self {
             board,
-            stack: Vec::new(),
+            stack: HashStack::default(),
         }
     }
 }
@@ -70,45 +76,95 @@ impl GameRep for Shatranj {
 
     type Policy = PolicyNetwork;
     type Value = ValueNetwork<768, 8>;
+    type ValueAccumulator = ValueAccumulator<Board, 8>;
+    type Undo = Undo<Board>;
+    const CP_SCALE: f32 = ValueNetwork::<768, 8>::SCALE as f32;
 
     const STARTPOS: &'static str = STARTPOS;
 
     const MAX_MOVES: usize = 512;
 
     fn default_mcts_params() -> MctsParams {
-        MctsParams::default()
+        let mut params = MctsParams::default();
+        params.set("report_cp_scale", Self::CP_SCALE / 100.0);
+        params
     }
 
     fn is_same(&self, other: &Self) -> bool {
-        self.board == other.board
+        self.board == other.board && self.stack.reversible() == other.stack.reversible()
     }
 
     fn conv_mov_to_str(&self, mov: Self::Move) -> String {
         mov.to_uci()
     }
 
+    fn conv_mov_to_san(&self, mov: Self::Move) -> String {
+        self.board.to_san(mov)
+    }
+
+    fn parse_san(&self, san: &str) -> Option<Self::Move> {
+        self.board.parse_san(san)
+    }
+
+    /// The promotion suffix is optional, as a pawn can only become a ferz,
+    /// and may be given as `f` for ferz rather than `q`.
+    fn parse_move(&self, mov: &str) -> Option<Self::Move> {
+        let mov = mov.strip_suffix(['q', 'f']).unwrap_or(mov);
+        let mut found = None;
+
+        self.map_legal_moves(|legal| {
+            if legal.to_uci().trim_end_matches('q') == mov {
+                found = Some(legal);
+            }
+        });
+
+        found
+    }
+
+    fn as_fen(&self) -> String {
+        self.board.as_fen()
+    }
+
     fn from_fen(fen: &str) -> Self {
         Self {
             board: Board::parse_fen(fen),
-            stack: Vec::new(),
+            stack: HashStack::default(),
         }
     }
 
+    fn try_from_fen(fen: &str) -> Result<Self, FenError> {
+        Ok(Self {
+            board: Board::try_parse_fen(fen)?,
+            stack: HashStack::default(),
+        })
+    }
+
     fn map_legal_moves<F: FnMut(Self::Move)>(&self, mut f: F) {
         self.board.map_legal_moves(&mut f);
     }
 
     fn game_state(&self) -> GameState {
-        self.board.game_state(&self.stack)
+        self.board.game_state(self.stack.reversible())
     }
 
-    fn make_move(&mut self, mov: Self::Move) {
-        self.stack.push(self.board.hash());
+    fn make_move(&mut self, mov: Self::Move) -> Self::Undo {
+        let board = self.board;
+        let undo = Undo::new(board, &self.stack);
         self.board.make(mov);
+        self.stack.push(board.hash(), self.board.halfm() == 0);
 
-        if self.board.halfm() == 0 {
-            self.stack.clear();
-        }
+        undo
+    }
+
+    fn make_move_untracked(&mut self, mov: Self::Move) -> Self::Undo {
+        let undo = Undo::new(self.board, &self.stack);
+        self.board.make(mov);
+
+        undo
+    }
+
+    fn unmake_move(&mut self, undo: Self::Undo) {
+        self.board = undo.restore(&mut self.stack);
     }
 
     fn stm(&self) -> usize {
@@ -123,7 +179,7 @@ impl GameRep for Shatranj {
         self.stm()
     }
 
-    fn get_policy_feats(&self) -> goober::SparseVector {
+    fn get_policy_feats(&self, _: &Self::Policy) -> goober::SparseVector {
         let mut feats = goober::SparseVector::with_capacity(32);
         self.board.features_map(|feat| feats.push(feat));
         feats
@@ -142,12 +198,25 @@ impl GameRep for Shatranj {
         value.eval(&self.board)
     }
 
-    fn perft(&self, depth: usize) -> u64 {
-        perft::<true, true>(&self.board, depth as u8)
+    fn refresh_value_accumulator(&self, acc: &mut Self::ValueAccumulator, value: &Self::Value) {
+        value.refresh(&self.board, acc);
+    }
+
+    fn update_value_accumulator(
+        &self,
+        base: &Self::ValueAccumulator,
+        acc: &mut Self::ValueAccumulator,
+        value: &Self::Value,
+    ) {
+        value.update(&self.board, base, acc);
+    }
+
+    fn get_value_from_accumulator(&self, acc: &Self::ValueAccumulator, value: &Self::Value) -> i32 {
+        value.eval_accumulator(acc)
     }
 
     fn display(&self, policy: &Self::Policy) {
-        let feats = self.get_policy_feats();
+        let feats = self.get_policy_feats(policy);
         let mut moves = Vec::new();
         let mut max = f32::NEG_INFINITY;
         self.map_legal_moves(|mov| {
@@ -220,30 +289,39 @@ impl GameRep for Shatranj {
     }
 }
 
-fn perft<const ROOT: bool, const BULK: bool>(pos: &Board, depth: u8) -> u64 {
-    let mut count = 0;
-    let leaf = depth == 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if BULK && !ROOT && leaf {
-        pos.map_legal_moves(&mut |_| count += 1);
-    } else {
-        pos.map_legal_moves(&mut |mov| {
-            let mut tmp = *pos;
-            tmp.make(mov);
+    #[test]
+    fn unmake_move_restores_position() {
+        let mut pos = Shatranj::from_fen("r3k2r/8/2b2q2/3N4/3n4/2B2Q2/8/R3K2R w - - 0 1");
 
-            let num = if leaf {
-                1
-            } else {
-                perft::<false, BULK>(&tmp, depth - 1)
-            };
+        // leave hashes on the stack for irreversible moves to clear
+        for mov in ["e1d1", "e8d8"] {
+            pos.make_move(pos.parse_move(mov).unwrap());
+        }
+
+        let before = pos.clone();
+        let mut moves = Vec::new();
+        pos.map_legal_moves(|mov| moves.push(mov));
 
-            count += num;
+        for mov in moves {
+            let undo = pos.make_move(mov);
 
-            if ROOT {
-                println!("{}: {num}", mov.to_uci());
+            let mut replies = Vec::new();
+            pos.map_legal_moves(|reply| replies.push(reply));
+
+            for reply in replies {
+                let undo = pos.make_move(reply);
+                pos.unmake_move(undo);
             }
-        });
-    }
 
-    count
+            pos.unmake_move(undo);
+
+            assert_eq!(pos.as_fen(), before.as_fen());
+            assert_eq!(pos.stack.reversible(), before.stack.reversible());
+            assert!(pos.is_same(&before));
+        }
+    }
 }
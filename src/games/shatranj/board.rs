@@ -1,4 +1,7 @@
-use crate::{games::GameState, pop_lsb};
+use crate::{
+    games::{check_counters, check_placement, FenError, GameState},
+    pop_lsb,
+};
 
 use super::{
     attacks::Attacks,
@@ -13,14 +16,16 @@ pub struct Board {
     hash: u64,
     stm: bool,
     halfm: u8,
+    fullm: u16,
 }
 
 impl Board {
-    pub fn from_raw(bb: [u64; 8], stm: bool, halfm: u8) -> Self {
+    pub fn from_raw(bb: [u64; 8], stm: bool, halfm: u8, fullm: u16) -> Self {
         Self {
             bb,
             stm,
             halfm,
+            fullm,
             hash: 0,
         }
     }
@@ -44,6 +49,11 @@ impl Board {
         self.halfm
     }
 
+    #[must_use]
+    pub fn fullm(&self) -> u16 {
+        self.fullm
+    }
+
     #[must_use]
     pub fn hash(&self) -> u64 {
         let mut hash = self.hash;
@@ -174,6 +184,39 @@ impl Board {
         }
     }
 
+    /// Calls `f(feat, add)` for each feature gained or lost since `old`,
+    /// which must have the same side to move.
+    pub fn feature_deltas<F: FnMut(usize, bool)>(&self, old: &Self, mut f: F) {
+        let flip = if self.stm { 56 } else { 0 };
+        let sides = [(self.boys(), old.boys(), 0), (self.opps(), old.opps(), 384)];
+
+        for (pc, (&bb, &old_bb)) in self
+            .bb
+            .iter()
+            .zip(old.bb.iter())
+            .skip(Piece::PAWN)
+            .enumerate()
+        {
+            for (occ, old_occ, offset) in sides {
+                let new = bb & occ;
+                let prev = old_bb & old_occ;
+
+                let mut added = new & !prev;
+                let mut removed = prev & !new;
+
+                while added > 0 {
+                    pop_lsb!(sq, added);
+                    f(offset + 64 * pc + usize::from(sq ^ flip), true);
+                }
+
+                while removed > 0 {
+                    pop_lsb!(sq, removed);
+                    f(offset + 64 * pc + usize::from(sq ^ flip), false);
+                }
+            }
+        }
+    }
+
     #[must_use]
     pub fn attackers_to_square(&self, sq: usize, side: usize, occ: u64) -> u64 {
         ((Attacks::knight(sq) & self.bb[Piece::KNIGHT])
@@ -268,6 +311,7 @@ impl Board {
         let moved = self.get_pc(1 << mov.from());
 
         // updating state
+        self.fullm += u16::from(self.stm);
         self.stm = !self.stm;
         self.halfm += 1;
 
@@ -324,9 +368,53 @@ impl Board {
         // side to move
         pos.stm = vec[1] == "b";
 
+        // halfmove clock and fullmove counter, after the unused castling and en passant fields
+        pos.halfm = vec.get(4).and_then(|hm| hm.parse().ok()).unwrap_or(0);
+        pos.fullm = vec.get(5).and_then(|fm| fm.parse().ok()).unwrap_or(1);
+
         pos
     }
 
+    /// Castling and en passant fields are ignored, as neither exists in shatranj.
+    pub fn try_parse_fen(fen: &str) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+
+        let placement = fields
+            .first()
+            .ok_or(FenError::MissingField("piece placement"))?;
+        let stm = fields
+            .get(1)
+            .ok_or(FenError::MissingField("side to move"))?;
+
+        check_placement(placement, 8, "PNBRQKpnbrqk")?;
+
+        if *stm != "w" && *stm != "b" {
+            return Err(FenError::InvalidSideToMove(stm.to_string()));
+        }
+
+        check_counters(fields.get(4..).unwrap_or_default())?;
+
+        let pos = Self::parse_fen(fen);
+
+        for side in [Side::WHITE, Side::BLACK] {
+            let count = (pos.bb[side] & pos.bb[Piece::KING]).count_ones();
+            if count != 1 {
+                return Err(FenError::KingCount { side, count });
+            }
+        }
+
+        if pos.bb[Piece::PAWN] & 0xFF000000000000FF > 0 {
+            return Err(FenError::PawnOnBackRank);
+        }
+
+        let nstm = pos.stm() ^ 1;
+        if pos.is_square_attacked(pos.ksq(nstm), nstm, pos.occ()) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        Ok(pos)
+    }
+
     pub fn map_legal_moves<F: FnMut(Move)>(&self, f: &mut F) {
         let pinned = self.pinned();
         let king_sq = self.king_index();
@@ -545,7 +633,7 @@ impl Board {
 
         fen.push(' ');
         fen.push(['w', 'b'][self.stm()]);
-        fen.push_str(" 0 1");
+        fen.push_str(&format!(" - - {} {}", self.halfm, self.fullm));
 
         fen
     }
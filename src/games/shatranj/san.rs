@@ -0,0 +1,188 @@
+use crate::games::san::{disambiguation, square_name, San};
+
+use super::{board::Board, consts::Piece, moves::Move};
+
+// a ferz is written as a queen and an alfil as a bishop, as in FENs
+const PIECES: [char; 6] = ['P', 'N', 'B', 'R', 'Q', 'K'];
+
+impl Board {
+    pub fn to_san(&self, mov: Move) -> String {
+        let from = usize::from(mov.from());
+        let to = usize::from(mov.to());
+        let pc = self.get_pc(1 << from);
+
+        let mut san = String::new();
+
+        if pc == Piece::PAWN {
+            if mov.is_capture() {
+                san.push_str(&square_name(from)[..1]);
+            }
+        } else {
+            let mut others = Vec::new();
+
+            self.map_legal_moves(&mut |other: Move| {
+                let other_from = usize::from(other.from());
+
+                if other_from != from
+                    && usize::from(other.to()) == to
+                    && self.get_pc(1 << other_from) == pc
+                {
+                    others.push(other_from);
+                }
+            });
+
+            san.push(PIECES[pc - Piece::PAWN]);
+            san.push_str(&disambiguation(from, &others));
+        }
+
+        if mov.is_capture() {
+            san.push('x');
+        }
+
+        san.push_str(&square_name(to));
+
+        if mov.is_promo() {
+            san.push_str("=Q");
+        }
+
+        let mut next = *self;
+        next.make(mov);
+
+        if next.in_check() {
+            let mut mated = true;
+            next.map_legal_moves(&mut |_| mated = false);
+            san.push(if mated { '#' } else { '+' });
+        }
+
+        san
+    }
+
+    /// A pawn can only promote to a ferz, so the promotion piece may be left out.
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let mut san = San::parse(san)?;
+
+        san.piece = match san.piece {
+            'F' => 'Q',
+            'A' => 'B',
+            piece => piece,
+        };
+
+        if san.castle.is_some() || !matches!(san.promo, None | Some('Q' | 'F')) {
+            return None;
+        }
+
+        let mut found = None;
+        let mut count = 0;
+
+        self.map_legal_moves(&mut |mov: Move| {
+            let from = usize::from(mov.from());
+            let pc = PIECES[self.get_pc(1 << from) - Piece::PAWN];
+
+            if san.matches(pc, from, usize::from(mov.to())) {
+                found = Some(mov);
+                count += 1;
+            }
+        });
+
+        if count == 1 {
+            found
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::games::{shatranj::Shatranj, GameRep};
+
+    fn san(fen: &str, mov: &str) -> String {
+        let pos = Shatranj::from_fen(fen);
+        pos.conv_mov_to_san(pos.parse_move(mov).unwrap())
+    }
+
+    #[test]
+    fn disambiguation() {
+        let knights = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san(knights, "b1d2"), "Nbd2");
+        assert_eq!(san(knights, "f3d2"), "Nfd2");
+
+        let rooks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(rooks, "a1a3"), "R1a3");
+        assert_eq!(san(rooks, "a5a3"), "R5a3");
+
+        // ferzes on a1, c1 and a3 all move to b2
+        let ferzes = "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1";
+        assert_eq!(san(ferzes, "a1b2"), "Qa1b2");
+        assert_eq!(san(ferzes, "a3b2"), "Q3b2");
+        assert_eq!(san(ferzes, "c1b2"), "Qcb2");
+
+        let pos = Shatranj::from_fen(ferzes);
+        let mov = pos.parse_move("c1b2").map(u16::from);
+        assert_eq!(pos.parse_san("Fcb2").map(u16::from), mov);
+    }
+
+    #[test]
+    fn checks() {
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn promotions() {
+        let pos = Shatranj::from_fen("8/1P2k3/8/8/8/8/8/4K3 w - - 0 1");
+        let mov = pos.parse_move("b7b8q").unwrap();
+
+        assert_eq!(pos.conv_mov_to_san(mov), "b8=Q");
+
+        for san in ["b8", "b8=Q", "b8F", "b8=f"] {
+            assert_eq!(pos.parse_san(san).map(u16::from), Some(mov.into()), "{san}");
+        }
+
+        assert_eq!(pos.parse_san("b8=N").map(u16::from), None);
+    }
+
+    #[test]
+    fn no_castling() {
+        let pos = Shatranj::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+        assert_eq!(pos.parse_san("O-O").map(u16::from), None);
+        assert_eq!(pos.parse_san("0-0-0").map(u16::from), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        // the chess bench positions are not all legal in shatranj
+        let suite = include_str!("../../../resources/shatranj-perft.txt");
+
+        for fen in suite
+            .lines()
+            .map(|line| line.split(';').next().unwrap().trim())
+        {
+            let mut pos = Shatranj::from_fen(fen);
+            let mut moves = Vec::new();
+            pos.map_legal_moves(|mov| moves.push(mov));
+
+            for mov in moves {
+                let undo = pos.make_move(mov);
+
+                pos.map_legal_moves(|reply| {
+                    let san = pos.conv_mov_to_san(reply);
+                    assert_eq!(
+                        pos.parse_san(&san).map(u16::from),
+                        Some(reply.into()),
+                        "{fen} {san}"
+                    );
+                });
+
+                pos.unmake_move(undo);
+
+                let san = pos.conv_mov_to_san(mov);
+                assert_eq!(
+                    pos.parse_san(&san).map(u16::from),
+                    Some(mov.into()),
+                    "{fen} {san}"
+                );
+            }
+        }
+    }
+}
@@ -5,9 +5,9 @@ mod tree;
 mod value;
 
 pub use comm::UciLike;
-pub use games::{ataxx, chess, shatranj, GameRep, GameState};
-pub use mcts::{Limits, MctsParams, Searcher};
-pub use tree::Tree;
+pub use games::{ataxx, chess, shatranj, FenError, GameRep, GameState};
+pub use mcts::{Limits, MctsParams, MoveSelection, Searcher};
+pub use tree::{Freed, Tree};
 pub use value::ValueNetwork;
 
 // Macro for calculating tables (until const fn pointers are stable).
@@ -5,17 +5,103 @@ use helpers::SearchHelpers;
 pub use params::MctsParams;
 
 use crate::{
-    games::{GameRep, GameState},
-    tree::{Node, Tree},
+    games::{wdl_to_cp, GameRep, GameState},
+    tree::{Edge, Freed, Node, Tree},
 };
 
-use std::time::Instant;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
 
 #[derive(Clone, Copy)]
 pub struct Limits {
     pub max_time: Option<u128>,
     pub max_depth: usize,
     pub max_nodes: usize,
+    /// Search until a mate in at most this many moves is proven.
+    pub max_mate: Option<usize>,
+    /// Search until stopped, ignoring every other limit.
+    pub infinite: bool,
+    /// Stop once this many nodes have been searched, as soon
+    /// as the most visited move is also the best move.
+    pub soft_nodes: Option<usize>,
+}
+
+impl Limits {
+    /// Time in milliseconds to spend on a move, given the time left on the clock,
+    /// the increment and the number of moves until the next time control.
+    pub fn move_time(time: u128, inc: Option<u128>, movestogo: Option<u128>) -> u128 {
+        let moves = movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+        let base = time / moves + inc.unwrap_or(0) * 3 / 4;
+
+        // the increment is only added once the move has been made, and
+        // some of the clock is kept back for delays in the GUI and IO
+        base.min(time * MAX_CLOCK_PERCENT / 100)
+    }
+}
+
+// moves assumed to be left when the time control doesn't give `movestogo`
+const DEFAULT_MOVES_TO_GO: u128 = 30;
+
+// most of the time left on the clock that a single move may use
+const MAX_CLOCK_PERCENT: u128 = 75;
+
+// z-score used for the lower confidence bound of Q
+const LCB_Z: f32 = 1.96;
+
+// minimum time between periodic search reports, in milliseconds
+const REPORT_INTERVAL: u128 = 1000;
+
+// moves with fewer than this fraction of the most visited
+// move's visits are not considered by hybrid selection
+const HYBRID_MIN_VISITS: f32 = 0.25;
+
+/// How the best move is chosen once search has finished.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MoveSelection {
+    /// Highest Q.
+    #[default]
+    Q,
+    /// Most visits.
+    Visits,
+    /// Highest lower confidence bound of Q.
+    Lcb,
+    /// Highest Q among sufficiently visited moves.
+    Hybrid,
+}
+
+impl MoveSelection {
+    pub const OPTIONS: [(&'static str, Self); 4] = [
+        ("Q", Self::Q),
+        ("Visits", Self::Visits),
+        ("LCB", Self::Lcb),
+        ("Hybrid", Self::Hybrid),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::OPTIONS
+            .iter()
+            .find(|(option, _)| *option == name)
+            .map(|&(_, selection)| selection)
+    }
+
+    /// Score of an ongoing or drawn edge with value `q`, where `max_visits`
+    /// is the most visits of any of its siblings. Scores lie in (-1, 1].
+    pub fn score(self, edge: &Edge, q: f32, max_visits: u32) -> f32 {
+        match self {
+            Self::Q => q,
+            Self::Visits => edge.visits() as f32 / max_visits as f32,
+            Self::Lcb => q - LCB_Z * (edge.var() / edge.visits() as f32).sqrt(),
+            Self::Hybrid => {
+                if (edge.visits() as f32) < HYBRID_MIN_VISITS * max_visits as f32 {
+                    q - 1.0
+                } else {
+                    q
+                }
+            }
+        }
+    }
 }
 
 pub struct Searcher<'a, T: GameRep> {
@@ -24,6 +110,27 @@ pub struct Searcher<'a, T: GameRep> {
     params: MctsParams,
     policy: &'a T::Policy,
     value: &'a T::Value,
+    // value accumulators along the current selection path,
+    // tagged with the (ptr, hash) of the node they belong to
+    accs: Vec<(i32, u64, T::ValueAccumulator)>,
+    // hashes of the positions on the current selection path,
+    // used to detect cycles in graph search
+    path: Vec<u64>,
+    move_selection: MoveSelection,
+    // value of a draw for the side to move at the root
+    draw_value: f32,
+    // if not empty, only these moves are searched at the root
+    search_moves: Vec<u16>,
+    // set from another thread to stop the search
+    abort: Option<&'a AtomicBool>,
+    // statistics of the current search, for reporting
+    seldepth: usize,
+    hash_hits: usize,
+    curr_action: usize,
+    // best move, with the time and nodes at which it became best,
+    // only tracked for EPD test suites
+    track_best_move: bool,
+    best_move: Option<(u16, u128, usize)>,
 }
 
 impl<'a, T: GameRep> Searcher<'a, T> {
@@ -34,15 +141,48 @@ impl<'a, T: GameRep> Searcher<'a, T> {
         policy: &'a T::Policy,
         value: &'a T::Value,
     ) -> Self {
+        // contempt is given in pawns
+        let draw_value = T::cp_to_wdl(-100.0 * params.contempt());
+
         Self {
             root_position,
             tree,
             params,
             policy,
             value,
+            accs: Vec::new(),
+            path: Vec::new(),
+            move_selection: MoveSelection::default(),
+            draw_value,
+            search_moves: Vec::new(),
+            abort: None,
+            seldepth: 0,
+            hash_hits: 0,
+            curr_action: 0,
+            track_best_move: false,
+            best_move: None,
         }
     }
 
+    pub fn set_move_selection(&mut self, move_selection: MoveSelection) {
+        self.move_selection = move_selection;
+    }
+
+    /// Records when the best move last changed, for `best_move_found`.
+    pub fn set_track_best_move(&mut self) {
+        self.track_best_move = true;
+    }
+
+    /// Search stops as soon as `abort` is set, whatever the limits.
+    pub fn set_abort_signal(&mut self, abort: &'a AtomicBool) {
+        self.abort = Some(abort);
+    }
+
+    /// Restricts search at the root to `moves`, ignoring any that are illegal.
+    pub fn set_search_moves(&mut self, moves: &[T::Move]) {
+        self.search_moves = moves.iter().map(|&mov| mov.into()).collect();
+    }
+
     pub fn search(
         &mut self,
         limits: Limits,
@@ -52,42 +192,108 @@ impl<'a, T: GameRep> Searcher<'a, T> {
     ) -> (T::Move, f32) {
         let timer = Instant::now();
 
+        let first_draw_value = if self.root_position.stm() == 0 {
+            self.draw_value
+        } else {
+            1.0 - self.draw_value
+        };
+
+        self.tree.set_draw_value(first_draw_value);
+
         // attempt to reuse the current tree stored in memory
-        self.tree.try_use_subtree(&self.root_position, prev_board);
-        let node = self.tree.root_node();
+        let mut freed =
+            self.tree
+                .try_use_subtree(&self.root_position, prev_board, &self.params, self.policy);
+        let mut node = self.tree.root_node();
+
+        // a root restricted by an earlier search would keep excluding moves
+        let mut legal_moves = 0;
+        self.root_position.map_legal_moves(|_| legal_moves += 1);
+
+        if self.tree[node].has_children()
+            && self.tree[node].num_edges() != legal_moves
+            && !self
+                .tree
+                .restore_root_moves(&self.root_position, &self.params, self.policy)
+        {
+            freed += self.tree.reset_root(&self.root_position);
+            node = self.tree.root_node();
+        }
 
         // relabel root policies with root PST value
         if self.tree[node].has_children() {
-            self.tree[node].relabel_policy(&self.root_position, &self.params, self.policy);
-        } else {
-            self.tree[node].expand::<T, true>(&self.root_position, &self.params, self.policy);
+            self.tree
+                .relabel_policy(node, &self.root_position, &self.params, self.policy, 0);
+        } else if !self
+            .tree
+            .expand_node(node, &self.root_position, &self.params, self.policy, 0)
+        {
+            // no space left by the reused tree, so start from scratch
+            freed += self.tree.reset_root(&self.root_position);
+            node = self.tree.root_node();
+            self.tree
+                .expand_node(node, &self.root_position, &self.params, self.policy, 0);
+        }
+
+        // `go searchmoves`, unless none of the moves are legal
+        if self
+            .tree
+            .actions(node)
+            .iter()
+            .any(|edge| self.search_moves.contains(&edge.mov()))
+        {
+            freed += self.tree.restrict_root_moves(&self.search_moves);
+        }
+
+        if uci_output && freed != Freed::default() {
+            println!(
+                "info string freed {} nodes and {} edges",
+                freed.nodes, freed.edges
+            );
         }
 
         let mut nodes = 0;
         let mut depth = 0;
         let mut cumulative_depth = 0;
+        let mut last_report = Instant::now();
+
+        self.seldepth = 0;
+        self.hash_hits = 0;
+        self.best_move = None;
+
+        let track_best_move = self.track_best_move;
+        let mut pos = self.root_position.clone();
 
         // search loop
         loop {
-            let mut pos = self.root_position.clone();
             let mut this_depth = 0;
-            self.perform_one_iteration(&mut pos, self.tree.root_node(), &mut this_depth);
+            self.perform_one_iteration(&mut pos, self.tree.root_node(), -1, 0, &mut this_depth);
 
             cumulative_depth += this_depth - 1;
 
-            // proven checkmate
-            if self.tree[self.tree.root_node()].is_terminal() {
-                break;
-            }
+            // the first iteration only visits the root, and a
+            // move can't be chosen until one of its children has been
+            if nodes > 0 {
+                if self
+                    .abort
+                    .is_some_and(|abort| abort.load(Ordering::Relaxed))
+                {
+                    break;
+                }
 
-            if nodes >= limits.max_nodes {
-                break;
+                if !limits.infinite && self.limit_reached(&limits, nodes) {
+                    break;
+                }
             }
 
             nodes += 1;
 
+            if track_best_move && nodes % 256 == 0 {
+                self.track_best_move(&timer, nodes);
+            }
+
             if let Some(time) = limits.max_time {
-                if nodes % 128 == 0 && timer.elapsed().as_millis() >= time {
+                if !limits.infinite && nodes % 128 == 0 && timer.elapsed().as_millis() >= time {
                     break;
                 }
             }
@@ -97,73 +303,186 @@ impl<'a, T: GameRep> Searcher<'a, T> {
             if avg_depth > depth {
                 depth = avg_depth;
 
-                if depth >= limits.max_depth {
+                if !limits.infinite && depth >= limits.max_depth {
                     break;
                 }
 
                 if uci_output {
                     self.search_report(depth, &timer, nodes);
+                    last_report = Instant::now();
                 }
             }
+
+            if uci_output
+                && nodes % 128 == 0
+                && last_report.elapsed().as_millis() >= REPORT_INTERVAL
+            {
+                self.search_report(depth.max(1), &timer, nodes);
+                self.progress_report(nodes);
+                last_report = Instant::now();
+            }
         }
 
         *total_nodes += nodes;
 
+        if track_best_move {
+            self.track_best_move(&timer, nodes);
+        }
+
         if uci_output {
             self.search_report(depth.max(1), &timer, nodes);
         }
 
-        let best_action = self.tree.get_best_child(self.tree.root_node());
+        let best_action =
+            self.tree
+                .get_best_child(self.tree.root_node(), self.move_selection, self.draw_value);
         let best_child = &self.tree.edge(self.tree.root_node(), best_action);
         (T::Move::from(best_child.mov()), best_child.q())
     }
 
-    fn perform_one_iteration(&mut self, pos: &mut T, ptr: i32, depth: &mut usize) -> f32 {
+    fn track_best_move(&mut self, timer: &Instant, nodes: usize) {
+        let root = self.tree.root_node();
+        let best = self
+            .tree
+            .get_best_child(root, self.move_selection, self.draw_value);
+        let mov = self.tree.edge(root, best).mov();
+
+        if self.best_move.is_none_or(|(prev, _, _)| prev != mov) {
+            self.best_move = Some((mov, timer.elapsed().as_millis(), nodes));
+        }
+    }
+
+    /// The time in milliseconds and number of nodes into the last search
+    /// at which the move it returned became the best move, and stayed so,
+    /// if `set_track_best_move` was called before it.
+    pub fn best_move_found(&self) -> (u128, usize) {
+        self.best_move
+            .map_or((0, 0), |(_, time, nodes)| (time, nodes))
+    }
+
+    fn limit_reached(&self, limits: &Limits, nodes: usize) -> bool {
+        let root = self.tree.root_node();
+
+        // proven result, unless looking for a shorter mate
+        match (self.tree[root].state(), limits.max_mate) {
+            (GameState::Ongoing, _) => {}
+            (GameState::Won(n), Some(moves)) if usize::from(n).div_ceil(2) > moves => {}
+            _ => return true,
+        }
+
+        if nodes >= limits.max_nodes {
+            return true;
+        }
+
+        match limits.soft_nodes {
+            Some(soft) if nodes >= soft => {
+                let best = self
+                    .tree
+                    .get_best_child(root, self.move_selection, self.draw_value);
+                let most_visited =
+                    self.tree
+                        .get_best_child(root, MoveSelection::Visits, self.draw_value);
+
+                best == most_visited
+            }
+            _ => false,
+        }
+    }
+
+    /// `parent` and `action` identify the edge that was taken to reach
+    /// `ptr`, as nodes can have more than one parent in graph search.
+    fn perform_one_iteration(
+        &mut self,
+        pos: &mut T,
+        ptr: i32,
+        parent: i32,
+        action: usize,
+        depth: &mut usize,
+    ) -> f32 {
         *depth += 1;
 
+        let ply = *depth - 1;
+
         self.tree.make_recently_used(ptr);
+        self.update_accumulator(pos, ptr, ply);
+        self.seldepth = self.seldepth.max(*depth);
 
         let hash = self.tree[ptr].hash();
-        let parent = self.tree[ptr].parent();
-        let action = self.tree[ptr].action();
+
+        self.path.truncate(ply);
+        self.path.push(hash);
+
+        // the next root is looked up by hash when reusing the tree,
+        // so nodes near the root are kept in the index
+        if ply <= usize::from(self.tree.reuse_depth()) {
+            self.tree.index_node(ptr);
+        }
 
         let mut child_state = GameState::Ongoing;
         let pvisits = self.tree.edge(parent, action).visits();
 
-        let mut u = if self.tree[ptr].is_terminal() || pvisits == 0 {
+        // the root is still searched once proven, to look for shorter mates
+        let terminal = self.tree[ptr].is_terminal() && parent != -1;
+
+        // expand node on the second visit, treating it as a leaf if the
+        // rest of the current path leaves no space for its edges
+        let leaf = terminal
+            || pvisits == 0
+            || (self.tree[ptr].is_not_expanded()
+                && !self
+                    .tree
+                    .expand_node(ptr, pos, &self.params, self.policy, ply));
+
+        let mut u = if leaf {
             // probe hash table to use in place of network
             if self.tree[ptr].state() == GameState::Ongoing {
-                if let Some(entry) = self.tree.probe_hash(hash) {
-                    1.0 - entry.wins / entry.visits as f32
+                if let Some(q) = self.shared_node_value(ptr) {
+                    q
+                } else if let Some(entry) = self.tree.probe_hash(hash) {
+                    self.hash_hits += 1;
+                    1.0 - entry.q
                 } else {
-                    self.get_utility(ptr, pos)
+                    self.get_utility(ptr, pos, ply)
                 }
             } else {
-                self.get_utility(ptr, pos)
+                self.get_utility(ptr, pos, ply)
             }
         } else {
-            // expand node on the second visit
-            if self.tree[ptr].is_not_expanded() {
-                self.tree[ptr].expand::<T, false>(pos, &self.params, self.policy);
-            }
-
             // select action to take via PUCT
-            let action = self.pick_action(ptr);
+            let child = self.pick_action(ptr, parent, action);
 
-            let edge = self.tree.edge(ptr, action);
-            pos.make_move(T::Move::from(edge.mov()));
+            if parent == -1 {
+                self.curr_action = child;
+            }
+
+            let edge = self.tree.edge(ptr, child);
+            let undo = pos.make_move(T::Move::from(edge.mov()));
 
             let mut child_ptr = edge.ptr();
 
-            // create and push node if not present
-            if child_ptr == -1 {
+            if self.tree.graph_search() {
+                child_ptr = self.get_graph_child(pos, ptr, child, ply);
+            } else if child_ptr == -1 {
+                // create and push node if not present
                 let state = pos.game_state();
-                child_ptr = self.tree.push(Node::new(state, pos.hash(), ptr, action));
-                self.tree.edge_mut(ptr, action).set_ptr(child_ptr);
+                child_ptr = self.tree.push(Node::new(state, pos.hash(), ptr, child));
+                self.tree.edge_mut(ptr, child).set_ptr(child_ptr);
             }
 
-            let u = self.perform_one_iteration(pos, child_ptr, depth);
-            child_state = self.tree[child_ptr].state();
+            let u = if child_ptr == -1 {
+                // position already occurs on the current path, score
+                // the cycle as a draw rather than searching it again
+                let u = 1.0 - self.draw_value(pos);
+                self.tree.edge_mut(ptr, child).update(u);
+                u
+            } else {
+                let u = self.perform_one_iteration(pos, child_ptr, ptr, child, depth);
+                child_state = self.tree[child_ptr].state();
+
+                u
+            };
+
+            pos.unmake_move(undo);
 
             u
         };
@@ -173,47 +492,166 @@ impl<'a, T: GameRep> Searcher<'a, T> {
         self.tree.edge_mut(parent, action).update(u);
 
         let edge = self.tree.edge(parent, action);
-        self.tree.push_hash(hash, edge.visits(), edge.wins());
+        self.tree.push_hash(hash, edge.visits(), edge.q());
 
-        self.tree.propogate_proven_mates(ptr, child_state);
+        self.tree.propagate_proven_states(ptr, child_state);
 
         self.tree.make_recently_used(ptr);
 
         u
     }
 
-    fn get_utility(&self, ptr: i32, pos: &T) -> f32 {
+    /// Finds the node reached by taking `action` from `ptr` in graph search,
+    /// sharing nodes between transpositions whose result does not depend on
+    /// the path taken to reach them. Returns -1 if the position is already
+    /// on the current selection path.
+    fn get_graph_child(&mut self, pos: &T, ptr: i32, action: usize, ply: usize) -> i32 {
+        let hash = pos.hash();
+        let state = pos.game_state();
+
+        let on_path = self.path[..=ply].contains(&hash);
+
+        if state == GameState::Ongoing && on_path {
+            return -1;
+        }
+
+        // draws can come from repetitions or other history the hash does
+        // not include, so draw nodes are never shared, and a shared node is
+        // only valid if it is not a draw along this path either. Proven wins
+        // and losses can depend on repetitions along the path by which they
+        // were proven, so are only trusted from the parent that created them.
+        let is_valid = |node: &Node| {
+            let owned = node.parent() == ptr && node.action() == action;
+
+            node.hash() == hash
+                && match state {
+                    GameState::Ongoing if owned => node.state() != GameState::Draw,
+                    GameState::Ongoing => node.state() == GameState::Ongoing,
+                    _ => node.state() == state,
+                }
+        };
+
+        let child_ptr = self.tree.edge(ptr, action).ptr();
+
+        // pointers to pruned nodes are not cleared if the
+        // node was not pruned from this parent
+        if child_ptr != -1 && is_valid(&self.tree[child_ptr]) {
+            return child_ptr;
+        }
+
+        let shared = if state == GameState::Ongoing {
+            self.tree
+                .lookup_node(hash)
+                .filter(|&shared| is_valid(&self.tree[shared]))
+        } else {
+            None
+        };
+
+        let child_ptr =
+            shared.unwrap_or_else(|| self.tree.push(Node::new(state, hash, ptr, action)));
+
+        self.tree.edge_mut(ptr, action).set_ptr(child_ptr);
+
+        child_ptr
+    }
+
+    /// Value of a node that has already been searched through
+    /// another parent in graph search.
+    fn shared_node_value(&self, ptr: i32) -> Option<f32> {
+        if !self.tree.graph_search() {
+            return None;
+        }
+
+        let (visits, wins) = self.tree.actions(ptr).iter().fold((0, 0.0), |(v, w), a| {
+            let visits = f64::from(a.visits());
+            (v + a.visits() as u64, w + visits * f64::from(a.q()))
+        });
+
+        if visits > 0 {
+            Some((wins / visits as f64) as f32)
+        } else {
+            None
+        }
+    }
+
+    fn update_accumulator(&mut self, pos: &T, ptr: i32, ply: usize) {
+        let hash = self.tree[ptr].hash();
+
+        if ply == self.accs.len() {
+            self.accs.push((-1, 0, T::ValueAccumulator::default()));
+        }
+
+        // still valid from a previous iteration
+        let (acc_ptr, acc_hash, _) = self.accs[ply];
+        if acc_ptr == ptr && acc_hash == hash {
+            return;
+        }
+
+        // positions two plies apart have the same side to move,
+        // so only differ by a handful of features
+        if ply >= 2 {
+            let (before, after) = self.accs.split_at_mut(ply);
+            pos.update_value_accumulator(&before[ply - 2].2, &mut after[0].2, self.value);
+        } else {
+            pos.refresh_value_accumulator(&mut self.accs[ply].2, self.value);
+        }
+
+        self.accs[ply].0 = ptr;
+        self.accs[ply].1 = hash;
+    }
+
+    fn get_utility(&self, ptr: i32, pos: &T, ply: usize) -> f32 {
         match self.tree[ptr].state() {
-            GameState::Ongoing => pos.get_value_wdl(self.value),
-            GameState::Draw => 0.5,
+            GameState::Ongoing => pos.get_value_wdl_from_accumulator(&self.accs[ply].2, self.value),
+            GameState::Draw => self.draw_value(pos),
             GameState::Lost(_) => 0.0,
             GameState::Won(_) => 1.0,
         }
     }
 
-    fn pick_action(&self, ptr: i32) -> usize {
+    /// Value of a draw for the side to move in `pos`.
+    fn draw_value(&self, pos: &T) -> f32 {
+        if pos.stm() == self.root_position.stm() {
+            self.draw_value
+        } else {
+            1.0 - self.draw_value
+        }
+    }
+
+    fn pick_action(&self, ptr: i32, parent: i32, action: usize) -> usize {
         if !self.tree[ptr].has_children() {
             panic!("trying to pick from no children!");
         }
 
-        let node = &self.tree[ptr];
-        let edge = self.tree.edge(node.parent(), node.action());
+        let edge = self.tree.edge(parent, action);
 
         let cpuct = SearchHelpers::get_cpuct(&self.params, edge);
         let fpu = SearchHelpers::get_fpu(edge);
 
-        let expl = cpuct * (edge.visits().max(1) as f32).sqrt();
+        // a shared node may have been visited more often through other parents
+        let mut visits = u64::from(edge.visits());
+        if self.tree.graph_search() {
+            let child_visits = self
+                .tree
+                .actions(ptr)
+                .iter()
+                .map(|a| u64::from(a.visits()))
+                .sum();
+            visits = visits.max(child_visits);
+        }
+
+        let expl = cpuct * (visits.max(1) as f32).sqrt();
 
         self.tree.get_best_child_by_key(ptr, |action| {
             let q = SearchHelpers::get_action_value(action, fpu);
-            let u = expl * action.policy() / (1 + action.visits()) as f32;
+            let u = expl * action.policy() / (1.0 + action.visits() as f32);
 
             q + u
         })
     }
 
     fn search_report(&self, depth: usize, timer: &Instant, nodes: usize) {
-        print!("info depth {depth} ");
+        print!("info depth {depth} seldepth {} ", self.seldepth);
         let (pv_line, score) = self.get_pv(depth);
 
         if score > 1.0 {
@@ -221,16 +659,17 @@ impl<'a, T: GameRep> Searcher<'a, T> {
         } else if score < 0.0 {
             print!("score mate -{} ", pv_line.len() / 2);
         } else {
-            let cp = -400.0 * (1.0 / score.clamp(0.0, 1.0) - 1.0).ln();
+            let cp = wdl_to_cp(score, self.params.report_scale());
             print!("score cp {cp:.0} ");
         }
 
         let elapsed = timer.elapsed();
         let nps = nodes as f32 / elapsed.as_secs_f32();
         let ms = elapsed.as_millis();
-        let hf = self.tree.len() * 1000 / self.tree.cap();
+        let hf = self.tree.hashfull();
 
-        print!("time {ms} nodes {nodes} nps {nps:.0} hashfull {hf} pv");
+        // there is no tablebase probing, but the field is still reported
+        print!("time {ms} nodes {nodes} nps {nps:.0} hashfull {hf} tbhits 0 pv");
 
         for mov in pv_line {
             print!(" {}", self.root_position.conv_mov_to_str(mov));
@@ -239,10 +678,26 @@ impl<'a, T: GameRep> Searcher<'a, T> {
         println!();
     }
 
+    fn progress_report(&self, nodes: usize) {
+        let root = self.tree.root_node();
+        let mov = self.tree.edge(root, self.curr_action).mov();
+
+        println!(
+            "info currmove {} currmovenumber {}",
+            self.root_position.conv_mov_to_str(mov.into()),
+            self.curr_action + 1
+        );
+
+        let rate = self.hash_hits as f32 * 100.0 / nodes.max(1) as f32;
+        println!("info string hash hits {} ({rate:.1}%)", self.hash_hits);
+    }
+
     fn get_pv(&self, mut depth: usize) -> (Vec<T::Move>, f32) {
         let mate = self.tree[self.tree.root_node()].is_terminal();
 
-        let idx = self.tree.get_best_child(self.tree.root_node());
+        let idx =
+            self.tree
+                .get_best_child(self.tree.root_node(), self.move_selection, self.draw_value);
         let mut action = self.tree.edge(self.tree.root_node(), idx);
 
         let score = if action.ptr() != -1 {
@@ -257,10 +712,15 @@ impl<'a, T: GameRep> Searcher<'a, T> {
         };
 
         let mut pv = Vec::new();
+        let mut draw = self.draw_value;
 
         while (mate || depth > 0) && action.ptr() != -1 {
             pv.push(T::Move::from(action.mov()));
-            let idx = self.tree.get_best_child(action.ptr());
+            draw = 1.0 - draw;
+
+            let idx = self
+                .tree
+                .get_best_child(action.ptr(), self.move_selection, draw);
 
             if idx == usize::MAX {
                 break;
@@ -278,10 +738,512 @@ impl<'a, T: GameRep> Searcher<'a, T> {
     }
 
     pub fn display_moves(&self) {
-        for action in self.tree[self.tree.root_node()].actions() {
+        for action in self.tree.actions(self.tree.root_node()) {
             let mov = self.root_position.conv_mov_to_str(action.mov().into());
             let q = action.q() * 100.0;
             println!("{mov} -> {q:.2}%");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ataxx::Ataxx,
+        chess::{Chess, QuantisedPolicyNetwork, ValueNetwork},
+    };
+
+    use std::time::Duration;
+
+    const MATE_IN_ONE: &str = "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1";
+
+    struct Nets {
+        policy: Box<QuantisedPolicyNetwork>,
+        value: Box<ValueNetwork>,
+    }
+
+    impl Nets {
+        fn zeroed() -> Self {
+            unsafe {
+                Self {
+                    policy: crate::boxed_and_zeroed(),
+                    value: crate::boxed_and_zeroed(),
+                }
+            }
+        }
+
+        fn searcher(&self, fen: &str) -> Searcher<'_, Chess> {
+            let pos = Chess::from_fen(fen);
+            let params = Chess::default_mcts_params();
+            Searcher::new(pos, Tree::new_mb(4), params, &self.policy, &self.value)
+        }
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            max_time: None,
+            max_depth: 256,
+            max_nodes: 1_000_000,
+            max_mate: None,
+            infinite: false,
+            soft_nodes: None,
+        }
+    }
+
+    fn search(searcher: &mut Searcher<Chess>, limits: Limits) -> (String, usize) {
+        let mut nodes = 0;
+        let (mov, _) = searcher.search(limits, false, &mut nodes, &None);
+        let mov = searcher.root_position.conv_mov_to_str(mov);
+        (mov, nodes)
+    }
+
+    #[test]
+    fn max_nodes() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_nodes: 500,
+            ..limits()
+        };
+
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert_eq!(nodes, 500);
+    }
+
+    #[test]
+    fn max_depth() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_depth: 2,
+            ..limits()
+        };
+
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert!(nodes < limits.max_nodes);
+    }
+
+    #[test]
+    fn max_time() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_time: Some(50),
+            max_nodes: usize::MAX,
+            ..limits()
+        };
+
+        let timer = Instant::now();
+        search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert!(timer.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn max_time_and_nodes() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_time: Some(60_000),
+            max_nodes: 200,
+            ..limits()
+        };
+
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert_eq!(nodes, 200);
+    }
+
+    #[test]
+    fn stops_once_proven() {
+        let nets = Nets::zeroed();
+
+        let (mov, nodes) = search(&mut nets.searcher(MATE_IN_ONE), limits());
+        assert_eq!(mov, "a1a8");
+        assert!(nodes < limits().max_nodes);
+    }
+
+    #[test]
+    fn max_mate() {
+        let nets = Nets::zeroed();
+
+        for moves in [1, 3] {
+            let limits = Limits {
+                max_mate: Some(moves),
+                ..limits()
+            };
+
+            let (mov, nodes) = search(&mut nets.searcher(MATE_IN_ONE), limits);
+            assert_eq!(mov, "a1a8");
+            assert!(nodes < limits.max_nodes);
+        }
+    }
+
+    #[test]
+    fn max_mate_too_short() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_mate: Some(0),
+            max_nodes: 300,
+            ..limits()
+        };
+
+        let (mov, nodes) = search(&mut nets.searcher(MATE_IN_ONE), limits);
+        assert_eq!(mov, "a1a8");
+        assert_eq!(nodes, 300);
+    }
+
+    #[test]
+    fn soft_nodes() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_nodes: 2000,
+            soft_nodes: Some(500),
+            ..limits()
+        };
+
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert!((500..=2000).contains(&nodes));
+    }
+
+    #[test]
+    fn soft_nodes_above_max_nodes() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_nodes: 300,
+            soft_nodes: Some(1000),
+            ..limits()
+        };
+
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert_eq!(nodes, 300);
+    }
+
+    #[test]
+    fn soft_nodes_and_time() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_time: Some(50),
+            max_nodes: usize::MAX,
+            soft_nodes: Some(usize::MAX),
+            ..limits()
+        };
+
+        let timer = Instant::now();
+        search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert!(timer.elapsed() >= Duration::from_millis(50));
+
+        let limits = Limits {
+            max_time: Some(60_000),
+            soft_nodes: Some(500),
+            ..limits
+        };
+
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert!(nodes >= 500);
+        assert!(timer.elapsed() < Duration::from_millis(60_000));
+    }
+
+    #[test]
+    fn soft_nodes_and_depth() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_depth: 2,
+            soft_nodes: Some(usize::MAX),
+            ..limits()
+        };
+
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert!(nodes < limits.max_nodes);
+    }
+
+    #[test]
+    fn max_mate_and_soft_nodes() {
+        let nets = Nets::zeroed();
+
+        // the mate is proven before the soft limit is reached
+        let limits = Limits {
+            max_mate: Some(1),
+            soft_nodes: Some(500_000),
+            ..limits()
+        };
+
+        let (mov, nodes) = search(&mut nets.searcher(MATE_IN_ONE), limits);
+        assert_eq!(mov, "a1a8");
+        assert!(nodes < 500_000);
+
+        // no mate short enough, so the soft limit applies
+        let limits = Limits {
+            max_mate: Some(0),
+            soft_nodes: Some(300),
+            ..limits
+        };
+
+        let (mov, nodes) = search(&mut nets.searcher(MATE_IN_ONE), limits);
+        assert_eq!(mov, "a1a8");
+        assert!((300..limits.max_nodes).contains(&nodes));
+    }
+
+    #[test]
+    fn max_mate_and_depth() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_mate: Some(1),
+            max_depth: 2,
+            ..limits()
+        };
+
+        // no mate to be found, so the depth limit applies
+        let (_, nodes) = search(&mut nets.searcher(Chess::STARTPOS), limits);
+        assert!(nodes < limits.max_nodes);
+    }
+
+    #[test]
+    fn move_time() {
+        // sudden death, with and without increment
+        assert_eq!(Limits::move_time(30_000, None, None), 1000);
+        assert_eq!(Limits::move_time(30_000, Some(400), None), 1300);
+
+        // the time left is split over the moves to the next time control
+        assert_eq!(Limits::move_time(30_000, None, Some(10)), 3000);
+        assert_eq!(Limits::move_time(30_000, Some(400), Some(10)), 3300);
+
+        // never all of what is left on the clock
+        assert_eq!(Limits::move_time(30_000, None, Some(1)), 22_500);
+        assert_eq!(Limits::move_time(30_000, None, Some(0)), 22_500);
+        assert_eq!(Limits::move_time(1000, Some(2000), Some(1)), 750);
+    }
+
+    #[test]
+    fn smallest_tree() {
+        let nets = Nets::zeroed();
+        let params = Chess::default_mcts_params();
+        let mut searcher = Searcher::new(
+            Chess::default(),
+            Tree::new_mb(0),
+            params,
+            &nets.policy,
+            &nets.value,
+        );
+
+        let limits = Limits {
+            max_nodes: 3000,
+            ..limits()
+        };
+
+        let (_, nodes) = search(&mut searcher, limits);
+        assert_eq!(nodes, 3000);
+    }
+
+    #[test]
+    fn contempt_is_not_reused_for_the_other_side() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_nodes: 1000,
+            ..limits()
+        };
+
+        for (contempt, reused) in [(0.0, true), (0.5, false)] {
+            let mut params = Chess::default_mcts_params();
+            params.set("contempt", contempt);
+
+            let mut searcher = Searcher::new(
+                Chess::default(),
+                Tree::new_mb(4),
+                params.clone(),
+                &nets.policy,
+                &nets.value,
+            );
+
+            search(&mut searcher, limits);
+
+            let (tree, prev) = searcher.tree_and_board();
+            let mut pos = prev.clone();
+            pos.make_move(pos.parse_move("e2e4").unwrap());
+
+            let mut searcher = Searcher::new(pos, tree, params, &nets.policy, &nets.value);
+            let mut nodes = 0;
+            let limits = Limits {
+                max_nodes: 1,
+                ..limits
+            };
+
+            searcher.search(limits, false, &mut nodes, &Some(prev));
+            let (tree, _) = searcher.tree_and_board();
+            assert_eq!(tree.len() > 10, reused, "contempt {contempt}");
+        }
+    }
+
+    #[test]
+    fn search_moves_are_restored() {
+        let nets = Nets::zeroed();
+        let limits = Limits {
+            max_nodes: 1000,
+            ..limits()
+        };
+
+        let mut searcher = nets.searcher(Chess::STARTPOS);
+        search(&mut searcher, limits);
+
+        let root = searcher.tree.root_node();
+        let visits = |tree: &Tree| {
+            let actions = tree.actions(root);
+            let sum = actions.iter().map(|edge| edge.visits()).sum::<u32>();
+            (sum, tree.edge(-1, 0).visits())
+        };
+
+        let pos = &searcher.root_position;
+        let moves = ["e2e4", "d2d4"].map(|mov| pos.parse_move(mov).unwrap().into());
+        let len = searcher.tree.len();
+        let freed = searcher.tree.restrict_root_moves(&moves);
+
+        // the subtrees of the dropped moves are freed
+        assert!(freed.nodes > 0 && freed.edges > 0);
+        assert_eq!(searcher.tree.len(), len - freed.nodes);
+
+        // visits to the dropped moves no longer count towards the root
+        let (restricted, parent) = visits(&searcher.tree);
+        assert_eq!(searcher.tree[root].num_edges(), 2);
+        assert_eq!(parent, restricted);
+
+        let (tree, prev) = searcher.tree_and_board();
+        let mut searcher = Searcher::new(
+            prev.clone(),
+            tree,
+            Chess::default_mcts_params(),
+            &nets.policy,
+            &nets.value,
+        );
+
+        let limits = Limits {
+            max_nodes: 1,
+            ..limits
+        };
+
+        searcher.search(limits, false, &mut 0, &Some(prev));
+
+        // the subtrees of e4 and d4 are kept
+        assert_eq!(searcher.tree.root_node(), root);
+        assert_eq!(searcher.tree[root].num_edges(), 20);
+        assert!(visits(&searcher.tree).0 > restricted);
+    }
+
+    #[test]
+    fn abort() {
+        let nets = Nets::zeroed();
+        let abort = AtomicBool::new(true);
+
+        let mut searcher = nets.searcher(Chess::STARTPOS);
+        searcher.set_abort_signal(&abort);
+
+        let (_, nodes) = search(&mut searcher, limits());
+        assert_eq!(nodes, 1);
+    }
+
+    #[test]
+    fn infinite() {
+        let nets = Nets::zeroed();
+        let abort = AtomicBool::new(false);
+
+        // every other limit is ignored, including a proven root
+        let limits = Limits {
+            infinite: true,
+            max_time: Some(0),
+            max_depth: 1,
+            max_nodes: 1,
+            max_mate: Some(1),
+            soft_nodes: Some(1),
+        };
+
+        let timer = Instant::now();
+
+        let (mov, nodes) = std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(50));
+                abort.store(true, Ordering::Relaxed);
+            });
+
+            let mut searcher = nets.searcher(MATE_IN_ONE);
+            searcher.set_abort_signal(&abort);
+            search(&mut searcher, limits)
+        });
+
+        assert!(timer.elapsed() >= Duration::from_millis(50));
+        assert_eq!(mov, "a1a8");
+        assert!(nodes > 1);
+    }
+
+    /// Follows `mov` from `ptr` in graph search, as `perform_one_iteration` would.
+    fn graph_child<T: GameRep>(
+        searcher: &mut Searcher<T>,
+        pos: &mut T,
+        ptr: i32,
+        mov: &str,
+        ply: usize,
+    ) -> i32 {
+        if searcher.tree[ptr].is_not_expanded() {
+            let (params, policy) = (searcher.params.clone(), searcher.policy);
+            searcher.tree.expand_node(ptr, pos, &params, policy, ply);
+        }
+
+        let mov = pos.parse_move(mov).unwrap();
+        let action = searcher
+            .tree
+            .actions(ptr)
+            .iter()
+            .position(|edge| edge.mov() == mov.into())
+            .unwrap();
+
+        searcher.path.truncate(ply);
+        searcher.path.push(searcher.tree[ptr].hash());
+
+        pos.make_move(mov);
+        searcher.get_graph_child(pos, ptr, action, ply)
+    }
+
+    fn ataxx_graph_searcher<'a>(
+        policy: &'a <Ataxx as GameRep>::Policy,
+        value: &'a <Ataxx as GameRep>::Value,
+    ) -> Searcher<'a, Ataxx> {
+        let pos = Ataxx::default();
+        let mut tree = Tree::new_mb(4);
+        tree.set_graph_search(true);
+        tree.reset_root(&pos);
+
+        Searcher::new(pos, tree, Ataxx::default_mcts_params(), policy, value)
+    }
+
+    fn follow(searcher: &mut Searcher<Ataxx>, moves: &[&str]) -> i32 {
+        let mut pos = Ataxx::default();
+        let mut ptr = searcher.tree.root_node();
+
+        for (ply, mov) in moves.iter().enumerate() {
+            ptr = graph_child(searcher, &mut pos, ptr, mov, ply);
+        }
+
+        ptr
+    }
+
+    #[test]
+    fn transpositions_share_nodes() {
+        let (policy, value) = unsafe { (crate::boxed_and_zeroed(), crate::boxed_and_zeroed()) };
+        let mut searcher = ataxx_graph_searcher(&policy, &value);
+
+        let first = follow(&mut searcher, &["g2", "a2", "f1"]);
+        let second = follow(&mut searcher, &["f1", "a2", "g2"]);
+        assert_eq!(first, second);
+
+        // a node proven through one path is not trusted from another
+        searcher.tree[first].set_state(GameState::Won(1));
+        let third = follow(&mut searcher, &["f1", "a2", "g2"]);
+        assert_ne!(third, first);
+    }
+
+    #[test]
+    fn cycles_are_not_searched() {
+        let (policy, value) = unsafe { (crate::boxed_and_zeroed(), crate::boxed_and_zeroed()) };
+        let mut searcher = ataxx_graph_searcher(&policy, &value);
+
+        // returns to the root, which is only a draw on the third occurrence
+        let child = follow(&mut searcher, &["g1e1", "a1c1", "e1g1", "c1a1"]);
+        assert_eq!(child, -1);
+
+        let child = follow(&mut searcher, &["g1e1", "a1c1", "e1g1", "c1a2"]);
+        assert_ne!(child, -1);
+    }
+}
@@ -8,7 +8,7 @@ impl SearchHelpers {
         let mut cpuct = params.cpuct();
 
         // scale CPUCT as visits increase
-        cpuct *= 1.0 + (((parent.visits() + 8192) / 8192) as f32).ln();
+        cpuct *= 1.0 + ((1 + parent.visits() / 8192) as f32).ln();
 
         // scale CPUCT with variance of Q
         if parent.visits() > 1 {
@@ -63,7 +63,28 @@ macro_rules! make_mcts_params {
 
 make_mcts_params! {
     root_pst: 1.0, 1.0, 10.0,
+    pst_interior: 1.0, 0.5, 10.0,
+    pst_decay: 0.0, 0.0, 1.0,
     cpuct: 1.41, 0.1, 5.0,
     cpuct_var_weight: 0.0, 0.0, 2.0,
     cpuct_var_scale: 0.4, 0.0, 2.0,
+    contempt: 0.0, -1.0, 1.0,
+    report_cp_scale: 4.0, 0.5, 20.0,
+}
+
+impl MctsParams {
+    /// Policy softmax temperature for a node at the given depth,
+    /// decaying from `root_pst` at the root towards `pst_interior`.
+    pub fn pst(&self, depth: usize) -> f32 {
+        let interior = self.pst_interior();
+        let scale = self.pst_decay().powi(depth as i32);
+
+        interior + (self.root_pst() - interior) * scale
+    }
+
+    /// Centipawns per unit of the logit of the win probability in reported
+    /// scores, which only changes how scores are shown, not the search.
+    pub fn report_scale(&self) -> f32 {
+        100.0 * self.report_cp_scale()
+    }
 }
@@ -7,10 +7,51 @@ use hash::{HashEntry, HashTable};
 pub use node::Node;
 use std::time::Instant;
 
-use crate::games::{GameRep, GameState};
+use crate::{
+    games::{GameRep, GameState},
+    mcts::MoveSelection,
+    MctsParams,
+};
+
+/// Fraction of the memory given to a tree that is used by the hash table.
+pub const DEFAULT_HASH_FRACTION: f32 = 0.02;
+
+// expected number of edges per node, used to split memory
+// between the node and edge arenas
+const EDGES_PER_NODE: usize = 16;
+
+// smallest tree allocated however little memory it is given, so
+// that there is always space to expand nodes with the most moves
+const MIN_NODES: usize = 1024;
+
+/// Default number of plies below the previous root searched for the new root.
+pub const DEFAULT_REUSE_DEPTH: u8 = 4;
+
+// searching for the previous root below the new root is done
+// by move generation rather than following edges, so is kept shallow
+const REVERSE_REUSE_DEPTH: u8 = 2;
+
+/// Nodes and edges freed by garbage collection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Freed {
+    pub nodes: usize,
+    pub edges: usize,
+}
+
+impl std::ops::AddAssign for Freed {
+    fn add_assign(&mut self, rhs: Self) {
+        self.nodes += rhs.nodes;
+        self.edges += rhs.edges;
+    }
+}
 
 pub struct Tree {
     tree: Vec<Node>,
+    edges: Vec<Edge>,
+    // edges are bump allocated from the start of the arena, and
+    // compacted once there is no space left at the end of it
+    edges_top: usize,
+    edges_used: usize,
     hash: HashTable,
     root: i32,
     empty: i32,
@@ -18,6 +59,15 @@ pub struct Tree {
     lru_head: i32,
     lru_tail: i32,
     parent_edge: Edge,
+    // when searching a graph, transposed positions share a node,
+    // which is found by looking up its hash here, as is the
+    // next root when reusing the tree
+    graph: bool,
+    nodes_by_hash: Vec<i32>,
+    reuse_depth: u8,
+    // value of a draw for the first player, which
+    // every value stored in the tree depends on
+    draw_value: f32,
 }
 
 impl std::ops::Index<i32> for Tree {
@@ -36,20 +86,41 @@ impl std::ops::IndexMut<i32> for Tree {
 
 impl Tree {
     pub fn new_mb(mb: usize) -> Self {
-        let cap = mb * 1024 * 1024 / std::mem::size_of::<Node>();
-        Self::new(cap)
+        Self::with_hash_fraction(mb, DEFAULT_HASH_FRACTION)
     }
 
-    fn new(cap: usize) -> Self {
+    /// Splits `mb` megabytes between the node arena and the hash
+    /// table, with `hash_fraction` of it going to the hash table.
+    pub fn with_hash_fraction(mb: usize, hash_fraction: f32) -> Self {
+        let hash_mb = mb as f32 * hash_fraction;
+        let node_mb = mb as f32 - hash_mb;
+
+        let bytes = (node_mb * 1024.0 * 1024.0) as usize;
+        let per_node = std::mem::size_of::<Node>()
+            + EDGES_PER_NODE * std::mem::size_of::<Edge>()
+            + std::mem::size_of::<i32>();
+        let cap = bytes / per_node;
+
+        Self::new(cap.max(MIN_NODES), HashTable::new_mb(hash_mb))
+    }
+
+    fn new(cap: usize, hash: HashTable) -> Self {
         let mut tree = Self {
-            tree: vec![Node::new(GameState::Ongoing, 0, -1, 0); cap / 8],
-            hash: HashTable::new(cap / 16),
+            tree: vec![Node::new(GameState::Ongoing, 0, -1, 0); cap],
+            edges: vec![Edge::default(); cap * EDGES_PER_NODE],
+            edges_top: 0,
+            edges_used: 0,
+            hash,
             root: -1,
             empty: 0,
             used: 0,
             lru_head: -1,
             lru_tail: -1,
             parent_edge: Edge::new(0, 0, 0),
+            graph: false,
+            nodes_by_hash: vec![-1; cap],
+            reuse_depth: DEFAULT_REUSE_DEPTH,
+            draw_value: 0.5,
         };
 
         let end = tree.cap() as i32 - 1;
@@ -69,12 +140,7 @@ impl Tree {
         // tree is full, do some LRU pruning
         if new == -1 {
             new = self.lru_tail;
-            let parent = self[new].parent();
-            let action = self[new].action();
-
-            self.edge_mut(parent, action).set_ptr(-1);
-
-            self.delete(new);
+            self.evict(new);
         }
 
         assert_ne!(new, -1);
@@ -84,6 +150,7 @@ impl Tree {
         self[new] = node;
 
         self.append_to_lru(new);
+        self.index_node(new);
 
         if self.used == 1 {
             self.lru_tail = new;
@@ -92,16 +159,344 @@ impl Tree {
         new
     }
 
+    fn evict(&mut self, ptr: i32) {
+        let parent = self[ptr].parent();
+        let action = self[ptr].action();
+
+        // in a graph the parent that created this node may
+        // already have been pruned and its slot reused
+        if self.is_edge_to(parent, action, ptr) {
+            self.edge_mut(parent, action).set_ptr(-1);
+        }
+
+        let idx = self.hash_idx(self[ptr].hash());
+        if self.nodes_by_hash[idx] == ptr {
+            self.nodes_by_hash[idx] = -1;
+        }
+
+        self.delete(ptr);
+    }
+
+    /// Makes sure there is space at the end of the edge arena for `num` edges,
+    /// pruning least recently used nodes and compacting the arena if not.
+    /// Returns `false` if the nodes that can't be pruned leave too little space.
+    fn reserve_edges(&mut self, num: usize) -> bool {
+        if self.edges_top + num <= self.edges.len() {
+            return true;
+        }
+
+        // free up an eighth of the arena so that this is not
+        // needed again for a while, without pruning any nodes
+        // on the current search path, which are all more
+        // recently used than the root
+        let target = self.edges.len() - self.edges.len() / 8;
+
+        while self.edges_used + num > target && self.lru_tail != self.root {
+            self.evict(self.lru_tail);
+        }
+
+        self.compact_edges();
+
+        self.edges_top + num <= self.edges.len()
+    }
+
+    fn compact_edges(&mut self) {
+        let mut nodes = (0..self.cap() as i32)
+            .filter(|&ptr| self[ptr].has_children())
+            .collect::<Vec<_>>();
+
+        nodes.sort_unstable_by_key(|&ptr| self[ptr].edges().start);
+
+        let mut top = 0;
+
+        for ptr in nodes {
+            let edges = self[ptr].edges();
+            let num = edges.len();
+
+            self.edges.copy_within(edges, top);
+            self[ptr].set_edges(top, num);
+
+            top += num;
+        }
+
+        self.edges_top = top;
+    }
+
+    pub fn actions(&self, ptr: i32) -> &[Edge] {
+        &self.edges[self[ptr].edges()]
+    }
+
+    fn actions_mut(&mut self, ptr: i32) -> &mut [Edge] {
+        let edges = self[ptr].edges();
+        &mut self.edges[edges]
+    }
+
+    /// Adds an edge for every legal move of `pos`, returning `false`
+    /// and leaving the node unexpanded if there is no space for them.
+    pub fn expand_node<T: GameRep>(
+        &mut self,
+        ptr: i32,
+        pos: &T,
+        params: &MctsParams,
+        policy: &T::Policy,
+        depth: usize,
+    ) -> bool {
+        assert!(self[ptr].is_not_expanded());
+
+        if !self.reserve_edges(T::MAX_MOVES) {
+            return false;
+        }
+
+        let first = self.edges_top;
+        let edges = &mut self.edges[first..];
+
+        let mut max = f32::NEG_INFINITY;
+        let mut num = 0;
+
+        pos.map_policies(policy, |mov, policy| {
+            // trick for calculating policy before quantising
+            edges[num] = Edge::new(f32::to_bits(policy) as i32, mov.into(), 0);
+            num += 1;
+            max = max.max(policy);
+        });
+
+        let edges = &mut edges[..num];
+        let pst = params.pst(depth);
+        let mut total = 0.0;
+
+        for action in edges.iter_mut() {
+            let mut policy = f32::from_bits(action.ptr() as u32);
+
+            policy = ((policy - max) / pst).exp();
+
+            action.set_ptr(f32::to_bits(policy) as i32);
+
+            total += policy;
+        }
+
+        for action in edges.iter_mut() {
+            let policy = f32::from_bits(action.ptr() as u32) / total;
+            action.set_ptr(-1);
+            action.set_policy(policy);
+        }
+
+        self.edges_top += num;
+        self.edges_used += num;
+        self[ptr].set_edges(first, num);
+
+        true
+    }
+
+    pub fn relabel_policy<T: GameRep>(
+        &mut self,
+        ptr: i32,
+        pos: &T,
+        params: &MctsParams,
+        policy: &T::Policy,
+        depth: usize,
+    ) {
+        let actions = self.actions(ptr);
+        let mut max = f32::NEG_INFINITY;
+
+        let mut policies = Vec::with_capacity(actions.len());
+
+        // edges are kept in the order their moves were generated,
+        // though some may have been dropped by `restrict_root_moves`
+        pos.map_policies(policy, |mov, policy| {
+            if actions.get(policies.len()).map(Edge::mov) == Some(mov.into()) {
+                policies.push(policy);
+                max = max.max(policy);
+            }
+        });
+
+        assert_eq!(policies.len(), actions.len(), "edges do not match position");
+
+        let pst = params.pst(depth);
+        let mut total = 0.0;
+
+        for policy in &mut policies {
+            *policy = ((*policy - max) / pst).exp();
+            total += *policy;
+        }
+
+        for (i, action) in self.actions_mut(ptr).iter_mut().enumerate() {
+            action.set_policy(policies[i] / total);
+        }
+    }
+
+    /// Drops every root edge whose move is not in `moves`,
+    /// renormalising the policies of those that remain.
+    pub fn restrict_root_moves(&mut self, moves: &[u16]) -> Freed {
+        let root = self.root;
+        let first = self[root].edges().start;
+        let mut num = 0;
+
+        for i in self[root].edges() {
+            let edge = self.edges[i];
+
+            if !moves.contains(&edge.mov()) {
+                continue;
+            }
+
+            let child = edge.ptr();
+            if child != -1 && self[child].parent() == root && self[child].action() == i - first {
+                self[child].set_parent(root, num);
+            }
+
+            self.edges[first + num] = edge;
+            num += 1;
+        }
+
+        self.edges_used -= self[root].num_edges() - num;
+        self[root].set_edges(first, num);
+        self[root].set_state(GameState::Ongoing);
+        self.refresh_root_stats();
+
+        let total: f32 = self.actions(root).iter().map(Edge::policy).sum();
+
+        for action in self.actions_mut(root) {
+            let policy = if total > 0.0 {
+                action.policy() / total
+            } else {
+                1.0 / num as f32
+            };
+
+            action.set_policy(policy);
+        }
+
+        self.collect_garbage()
+    }
+
+    /// Gives a root restricted by `restrict_root_moves` an edge for every legal
+    /// move again, keeping the subtrees of the moves it still has. Returns
+    /// `false` if there is no space for the new edges.
+    pub fn restore_root_moves<T: GameRep>(
+        &mut self,
+        pos: &T,
+        params: &MctsParams,
+        policy: &T::Policy,
+    ) -> bool {
+        let root = self.root;
+
+        // reserve while the old edges are still attached, so that
+        // any children pruned to make space are unlinked from them
+        if !self.reserve_edges(T::MAX_MOVES) {
+            return false;
+        }
+
+        let old = self.actions(root).to_vec();
+        self.edges_used -= old.len();
+        self[root].set_edges(0, 0);
+        self[root].set_state(GameState::Ongoing);
+
+        assert!(self.expand_node(root, pos, params, policy, 0));
+
+        let first = self[root].edges().start;
+
+        for i in self[root].edges() {
+            let mov = self.edges[i].mov();
+
+            let Some(idx) = old.iter().position(|edge| edge.mov() == mov) else {
+                continue;
+            };
+
+            let mut edge = old[idx];
+            edge.set_policy(self.edges[i].policy());
+            self.edges[i] = edge;
+
+            let child = edge.ptr();
+            if child != -1 && self[child].parent() == root && self[child].action() == idx {
+                self[child].set_parent(root, i - first);
+            }
+        }
+
+        self.refresh_root_stats();
+
+        true
+    }
+
+    /// Recomputes the stats of the edge to the root from those of its children,
+    /// after the set of root edges has changed.
+    fn refresh_root_stats(&mut self) {
+        let (visits, q, var) = self.children_stats(self.root);
+        let q = if visits == 0 { 0.0 } else { 1.0 - q };
+        self.parent_edge.set_stats(visits, q, var);
+    }
+
+    /// Replaces the root with a fresh node for `pos`, freeing the old tree.
+    pub fn reset_root<T: GameRep>(&mut self, pos: &T) -> Freed {
+        let node = self.push(Node::new(GameState::Ongoing, pos.hash(), -1, 0));
+        self.make_root_node(node);
+        self.collect_garbage()
+    }
+
+    pub fn set_reuse_depth(&mut self, depth: u8) {
+        self.reuse_depth = depth;
+    }
+
+    pub fn reuse_depth(&self) -> u8 {
+        self.reuse_depth
+    }
+
+    /// Values found with contempt are only valid for the side it was
+    /// applied for, so the tree is cleared if the value of a draw changes.
+    pub fn set_draw_value(&mut self, draw_value: f32) {
+        if draw_value != self.draw_value {
+            self.clear();
+            self.draw_value = draw_value;
+        }
+    }
+
+    pub fn graph_search(&self) -> bool {
+        self.graph
+    }
+
+    /// Switching between tree and graph search invalidates
+    /// the current tree, so it is cleared.
+    pub fn set_graph_search(&mut self, graph: bool) {
+        self.graph = graph;
+        self.clear();
+    }
+
+    fn hash_idx(&self, hash: u64) -> usize {
+        (hash % self.nodes_by_hash.len() as u64) as usize
+    }
+
+    /// Finds the shared node for a position, if it is still in the tree.
+    pub fn lookup_node(&self, hash: u64) -> Option<i32> {
+        let ptr = self.nodes_by_hash[self.hash_idx(hash)];
+
+        if ptr != -1 && self[ptr].hash() == hash {
+            Some(ptr)
+        } else {
+            None
+        }
+    }
+
+    /// Makes `ptr` the node found when looking up its hash. Terminal
+    /// nodes are never looked up, as their state can depend on the path.
+    pub fn index_node(&mut self, ptr: i32) {
+        if self[ptr].state() == GameState::Ongoing {
+            let idx = self.hash_idx(self[ptr].hash());
+            self.nodes_by_hash[idx] = ptr;
+        }
+    }
+
+    fn is_edge_to(&self, parent: i32, action: usize, ptr: i32) -> bool {
+        parent == -1 || self.actions(parent).get(action).map(Edge::ptr) == Some(ptr)
+    }
+
     pub fn probe_hash(&self, hash: u64) -> Option<HashEntry> {
         self.hash.get(hash)
     }
 
-    pub fn push_hash(&mut self, hash: u64, visits: i32, wins: f32) {
-        self.hash.push(hash, visits, wins);
+    pub fn push_hash(&mut self, hash: u64, visits: u32, q: f32) {
+        self.hash.push(hash, visits, q);
     }
 
     pub fn delete(&mut self, ptr: i32) {
         self.remove_from_lru(ptr);
+        self.edges_used -= self[ptr].num_edges();
         self[ptr].clear();
 
         let empty = self.empty;
@@ -163,6 +558,17 @@ impl Tree {
         self.cap() - self.len()
     }
 
+    /// Permille of the memory used by nodes and edges that is in use.
+    pub fn hashfull(&self) -> usize {
+        let node_size = std::mem::size_of::<Node>();
+        let edge_size = std::mem::size_of::<Edge>();
+
+        let used = self.len() * node_size + self.edges_used * edge_size;
+        let total = self.cap() * node_size + self.edges.len() * edge_size;
+
+        used * 1000 / total
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -176,10 +582,16 @@ impl Tree {
         self.root = -1;
         self.empty = 0;
         self.used = 0;
+        self.edges_top = 0;
+        self.edges_used = 0;
         self.lru_head = -1;
         self.lru_tail = -1;
         self.parent_edge = Edge::new(0, 0, 0);
 
+        for ptr in &mut self.nodes_by_hash {
+            *ptr = -1;
+        }
+
         let end = self.cap() as i32 - 1;
 
         for i in 0..end {
@@ -191,8 +603,15 @@ impl Tree {
     }
 
     pub fn make_root_node(&mut self, node: i32) {
+        let parent = self[node].parent();
+        let action = self[node].action();
+
         self.root = node;
-        self.parent_edge = *self.edge(self[node].parent(), self[node].action());
+        self.parent_edge = if self.is_edge_to(parent, action, node) {
+            *self.edge(parent, action)
+        } else {
+            Edge::new(node, 0, 0)
+        };
         self[node].clear_parent();
         self[node].set_state(GameState::Ongoing);
     }
@@ -201,7 +620,7 @@ impl Tree {
         if ptr == -1 {
             &self.parent_edge
         } else {
-            &self[ptr].actions()[idx]
+            &self.actions(ptr)[idx]
         }
     }
 
@@ -209,51 +628,71 @@ impl Tree {
         if ptr == -1 {
             &mut self.parent_edge
         } else {
-            &mut self[ptr].actions_mut()[idx]
-        }
-    }
-
-    pub fn propogate_proven_mates(&mut self, ptr: i32, child_state: GameState) {
-        match child_state {
-            // if the child node resulted in a loss, then
-            // this node has a guaranteed win
-            GameState::Lost(n) => self[ptr].set_state(GameState::Won(n + 1)),
-            // if the child node resulted in a win, then check if there are
-            // any non-won children, and if not, guaranteed loss for this node
-            GameState::Won(n) => {
-                let mut proven_loss = true;
-                let mut max_win_len = n;
-                for action in self[ptr].actions() {
-                    if action.ptr() == -1 {
-                        proven_loss = false;
-                        break;
-                    } else if let GameState::Won(n) = self[action.ptr()].state() {
-                        max_win_len = n.max(max_win_len);
-                    } else {
-                        proven_loss = false;
-                        break;
-                    }
-                }
+            &mut self.actions_mut(ptr)[idx]
+        }
+    }
 
-                if proven_loss {
-                    self[ptr].set_state(GameState::Lost(max_win_len + 1));
+    /// Updates the proven state of a node after one of its children
+    /// has been proven. A node is won if any move leads to a lost
+    /// position for the opponent, taking the shortest such mate, and is
+    /// otherwise only proven once every move has been proven, being
+    /// drawn if any move draws and lost (in the longest time) if not.
+    pub fn propagate_proven_states(&mut self, ptr: i32, child_state: GameState) {
+        if child_state == GameState::Ongoing {
+            return;
+        }
+
+        let mut shortest_win = None;
+        let mut longest_loss = 0;
+        let mut draw = false;
+        let mut all_proven = true;
+
+        for action in self.actions(ptr) {
+            let state = if action.ptr() == -1 {
+                GameState::Ongoing
+            } else {
+                self[action.ptr()].state()
+            };
+
+            match state {
+                GameState::Lost(n) => {
+                    shortest_win = Some(shortest_win.map_or(n, |m: u8| m.min(n)));
                 }
+                GameState::Won(n) => longest_loss = longest_loss.max(n),
+                GameState::Draw => draw = true,
+                GameState::Ongoing => all_proven = false,
+            }
+        }
+
+        if let Some(n) = shortest_win {
+            self[ptr].set_state(GameState::Won(n.saturating_add(1)));
+        } else if all_proven {
+            if draw {
+                self[ptr].set_state(GameState::Draw);
+            } else {
+                self[ptr].set_state(GameState::Lost(longest_loss.saturating_add(1)));
             }
-            // nothing to do otherwise
-            _ => {}
         }
     }
 
-    pub fn try_use_subtree<T: GameRep>(&mut self, root: &T, prev_board: &Option<T>) {
+    pub fn try_use_subtree<T: GameRep>(
+        &mut self,
+        root: &T,
+        prev_board: &Option<T>,
+        params: &MctsParams,
+        policy: &T::Policy,
+    ) -> Freed {
         let t = Instant::now();
 
         if self.is_empty() {
             let node = self.push(Node::new(GameState::Ongoing, root.hash(), -1, 0));
             self.make_root_node(node);
 
-            return;
+            return Freed::default();
         }
 
+        let mut freed = Freed::default();
+
         println!("info string attempting to reuse tree");
 
         let mut found = false;
@@ -261,64 +700,222 @@ impl Tree {
         if let Some(board) = prev_board {
             println!("info string searching for subtree");
 
-            let root = self.recurse_find(self.root, board, root, 2);
+            let new_root = self.find_new_root(board, root);
 
-            if root != -1 && self[root].has_children() {
+            if new_root != -1 && self[new_root].has_children() {
                 found = true;
 
-                if root != self.root_node() {
-                    self.make_root_node(root);
+                if new_root != self.root_node() {
+                    self.make_root_node(new_root);
                     println!("info string found subtree");
+                    freed = self.collect_garbage();
                 } else {
                     println!("info string using current tree");
                 }
+            } else if self[self.root].has_children() {
+                // the previous root may instead be a descendant of the new root
+                let mut path = Vec::new();
+
+                if find_path(&mut root.clone(), board, REVERSE_REUSE_DEPTH, &mut path)
+                    && self.attach_above_root(root, &path, params, policy)
+                {
+                    found = true;
+
+                    println!("info string found previous root in subtree");
+                    freed = self.collect_garbage();
+                }
             }
         }
 
         if !found {
             println!("info string no subtree found");
-            let node = self.push(Node::new(GameState::Ongoing, root.hash(), -1, 0));
-            self.make_root_node(node);
+            freed = self.reset_root(root);
         }
 
         println!(
             "info string tree processing took {} microseconds",
             t.elapsed().as_micros()
         );
+
+        freed
     }
 
-    fn recurse_find<T: GameRep>(&self, start: i32, this_board: &T, board: &T, depth: u8) -> i32 {
-        if this_board.is_same(board) {
-            return start;
+    /// Makes `pos` the root of the tree, connected to the current root
+    /// by a chain of nodes following `path`. Returns `false` if there
+    /// is no space for the chain.
+    fn attach_above_root<T: GameRep>(
+        &mut self,
+        pos: &T,
+        path: &[T::Move],
+        params: &MctsParams,
+        policy: &T::Policy,
+    ) -> bool {
+        let old_root = self.root;
+
+        // the chain has only been searched through the previous root, so has
+        // the visits of its children, and their value for the side to move
+        let (visits, q, var) = self.children_stats(old_root);
+
+        let mut pos = pos.clone();
+        let mut ptr = self.push(Node::new(pos.game_state(), pos.hash(), -1, 0));
+        let new_root = ptr;
+
+        for (depth, &mov) in path.iter().enumerate() {
+            if !self.expand_node(ptr, &pos, params, policy, depth) {
+                return false;
+            }
+
+            let mov = mov.into();
+            let action = self
+                .actions(ptr)
+                .iter()
+                .position(|edge| edge.mov() == mov)
+                .unwrap();
+
+            pos.make_move(T::Move::from(mov));
+
+            let child = if depth + 1 == path.len() {
+                self[old_root].set_parent(ptr, action);
+                old_root
+            } else {
+                self.push(Node::new(pos.game_state(), pos.hash(), ptr, action))
+            };
+
+            // edges hold the value for the side that made the move
+            let stm = (path.len() - depth) % 2 == 1;
+            let edge = self.edge_mut(ptr, action);
+            edge.set_ptr(child);
+            edge.set_stats(visits, if stm { 1.0 - q } else { q }, var);
+
+            ptr = child;
         }
 
-        if start == -1 || depth == 0 {
-            return -1;
+        let stm = path.len() % 2 == 0;
+        self.root = new_root;
+        self.parent_edge = Edge::new(new_root, 0, 0);
+        self.parent_edge
+            .set_stats(visits, if stm { 1.0 - q } else { q }, var);
+
+        true
+    }
+
+    /// Total visits to the children of `ptr`, along with the
+    /// mean and variance of their results for the side to move.
+    fn children_stats(&self, ptr: i32) -> (u32, f64, f32) {
+        let actions = self.actions(ptr);
+        let visits = actions
+            .iter()
+            .map(|edge| u64::from(edge.visits()))
+            .sum::<u64>();
+
+        if visits == 0 {
+            return (0, 0.0, 0.0);
         }
 
-        let node = &self.tree[start as usize];
+        let total = visits as f64;
+        let weight = |edge: &Edge| f64::from(edge.visits()) / total;
+
+        let q = actions
+            .iter()
+            .map(|edge| weight(edge) * f64::from(edge.q()))
+            .sum::<f64>();
+        let var = actions
+            .iter()
+            .map(|edge| {
+                let delta = f64::from(edge.q()) - q;
+                weight(edge) * (f64::from(edge.var()) + delta * delta)
+            })
+            .sum::<f64>();
+
+        (visits.min(u64::from(u32::MAX)) as u32, q, var as f32)
+    }
+
+    /// Frees every node that can no longer be reached from the root,
+    /// rather than leaving them to be pruned by LRU during search.
+    fn collect_garbage(&mut self) -> Freed {
+        let nodes = self.len();
+        let edges = self.edges_used;
 
-        for action in node.actions() {
-            let child_idx = action.ptr();
-            let mut child_board = this_board.clone();
+        let mut reachable = vec![false; self.cap()];
+        let mut stack = vec![self.root];
+        reachable[self.root as usize] = true;
+
+        while let Some(ptr) = stack.pop() {
+            for action in self.actions(ptr) {
+                let child = action.ptr();
+
+                if child != -1 && !reachable[child as usize] {
+                    reachable[child as usize] = true;
+                    stack.push(child);
+                }
+            }
+        }
 
-            child_board.make_move(T::Move::from(action.mov()));
+        let mut ptr = self.lru_head;
 
-            let found = self.recurse_find(child_idx, &child_board, board, depth - 1);
+        while ptr != -1 {
+            let next = self[ptr].fwd_link();
 
-            if found != -1 {
-                return found;
+            if !reachable[ptr as usize] {
+                self.evict(ptr);
             }
+
+            ptr = next;
         }
 
-        -1
+        self.compact_edges();
+
+        Freed {
+            nodes: nodes - self.len(),
+            edges: edges - self.edges_used,
+        }
+    }
+
+    /// Looks up the node for `pos` by its hash, checking that it is at most
+    /// `reuse_depth` plies below the current root, which is at `prev`, and
+    /// that the moves leading to it from `prev` really do reach `pos`.
+    fn find_new_root<T: GameRep>(&self, prev: &T, pos: &T) -> i32 {
+        let Some(new_root) = self.lookup_node(pos.hash()) else {
+            return -1;
+        };
+
+        let mut moves = Vec::new();
+        let mut ptr = new_root;
+
+        while ptr != self.root {
+            let parent = self[ptr].parent();
+            let action = self[ptr].action();
+
+            // parents in a graph can be pruned while their children are not
+            if moves.len() == usize::from(self.reuse_depth)
+                || parent == -1
+                || !self.is_edge_to(parent, action, ptr)
+            {
+                return -1;
+            }
+
+            moves.push(self.edge(parent, action).mov());
+            ptr = parent;
+        }
+
+        let mut board = prev.clone();
+
+        for &mov in moves.iter().rev() {
+            board.make_move(T::Move::from(mov));
+        }
+
+        if board.is_same(pos) {
+            new_root
+        } else {
+            -1
+        }
     }
 
     pub fn get_best_child_by_key<F: FnMut(&Edge) -> f32>(&self, ptr: i32, mut key: F) -> usize {
         let mut best_child = usize::MAX;
         let mut best_score = f32::NEG_INFINITY;
 
-        for (i, action) in self[ptr].actions().iter().enumerate() {
+        for (i, action) in self.actions(ptr).iter().enumerate() {
             let score = key(action);
 
             if score > best_score {
@@ -330,29 +927,47 @@ impl Tree {
         best_child
     }
 
-    pub fn get_best_child(&self, ptr: i32) -> usize {
+    /// `draw` is the value of a draw for the side to move at `ptr`.
+    pub fn get_best_child(&self, ptr: i32, selection: MoveSelection, draw: f32) -> usize {
+        let max_visits = self
+            .actions(ptr)
+            .iter()
+            .map(Edge::visits)
+            .max()
+            .unwrap_or(0);
+
         self.get_best_child_by_key(ptr, |child| {
             if child.visits() == 0 {
                 f32::NEG_INFINITY
             } else if child.ptr() != -1 {
                 match self[child.ptr()].state() {
-                    GameState::Lost(n) => 1.0 + f32::from(n),
+                    // prefer the shortest mate
+                    GameState::Lost(n) => 512.0 - f32::from(n),
                     GameState::Won(n) => f32::from(n) - 256.0,
-                    GameState::Draw => 0.5,
-                    GameState::Ongoing => child.q(),
+                    GameState::Draw => selection.score(child, draw, max_visits),
+                    GameState::Ongoing => selection.score(child, child.q(), max_visits),
                 }
             } else {
-                child.q()
+                selection.score(child, child.q(), max_visits)
             }
         })
     }
 
-    pub fn display<T: GameRep>(&self, idx: i32, depth: usize) {
+    /// `pos` is the position at `idx`, used to write moves in SAN.
+    pub fn display<T: GameRep>(&self, idx: i32, pos: &T, depth: usize) {
         let mut bars = vec![true; depth + 1];
-        self.display_recurse::<T>(Edge::new(idx, 0, 0), depth + 1, 0, &mut bars);
+        self.display_recurse(Edge::new(idx, 0, 0), pos, None, depth + 1, 0, &mut bars);
     }
 
-    fn display_recurse<T: GameRep>(&self, edge: Edge, depth: usize, ply: usize, bars: &mut [bool]) {
+    fn display_recurse<T: GameRep>(
+        &self,
+        edge: Edge,
+        pos: &T,
+        san: Option<String>,
+        depth: usize,
+        ply: usize,
+        bars: &mut [bool],
+    ) {
         let node = &self[edge.ptr()];
 
         if depth == 0 {
@@ -379,7 +994,7 @@ impl Tree {
                 print!("\u{2514}\u{2500}> ");
             }
 
-            let mov = T::Move::from(edge.mov()).to_string();
+            let mov = san.unwrap_or_default();
 
             println!(
                 "{mov} Q({:.2}%) N({}) P({:.2}%) S({})",
@@ -393,7 +1008,7 @@ impl Tree {
         }
 
         let mut active = Vec::new();
-        for &action in node.actions() {
+        for &action in self.actions(edge.ptr()) {
             if action.ptr() != -1 {
                 active.push(action);
             }
@@ -406,9 +1021,46 @@ impl Tree {
                 bars[ply] = false;
             }
             if action.visits() > 0 {
-                self.display_recurse::<T>(action, depth - 1, ply + 1, bars);
+                let mov = T::Move::from(action.mov());
+                let san = pos.conv_mov_to_san(mov);
+
+                let mut child = pos.clone();
+                child.make_move(mov);
+
+                self.display_recurse(action, &child, Some(san), depth - 1, ply + 1, bars);
             }
             bars[ply] = true;
         }
     }
 }
+
+/// Searches for a sequence of at most `depth` moves leading from `from` to `to`.
+fn find_path<T: GameRep>(from: &mut T, to: &T, depth: u8, path: &mut Vec<T::Move>) -> bool {
+    if from.hash() == to.hash() && from.is_same(to) {
+        return true;
+    }
+
+    if depth == 0 {
+        return false;
+    }
+
+    let mut moves = Vec::new();
+    from.map_legal_moves(|mov| moves.push(mov));
+
+    for mov in moves {
+        let undo = from.make_move(mov);
+        path.push(mov);
+
+        let found = find_path(from, to, depth - 1, path);
+
+        from.unmake_move(undo);
+
+        if found {
+            return true;
+        }
+
+        path.pop();
+    }
+
+    false
+}
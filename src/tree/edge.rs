@@ -1,11 +1,13 @@
+// q is kept in double precision, and the variance is tracked with
+// Welford's algorithm, so that both stay accurate after many visits
 #[derive(Clone, Copy, Debug)]
 pub struct Edge {
     ptr: i32,
     mov: u16,
-    policy: i16,
-    visits: i32,
-    wins: f32,
-    sq_wins: f32,
+    policy: u16,
+    visits: u32,
+    var: f32,
+    q: f64,
 }
 
 impl Default for Edge {
@@ -15,21 +17,21 @@ impl Default for Edge {
             mov: 0,
             policy: 0,
             visits: 0,
-            wins: 0.0,
-            sq_wins: 0.0,
+            var: 0.0,
+            q: 0.0,
         }
     }
 }
 
 impl Edge {
-    pub fn new(ptr: i32, mov: u16, policy: i16) -> Self {
+    pub fn new(ptr: i32, mov: u16, policy: u16) -> Self {
         Self {
             ptr,
             mov,
             policy,
             visits: 0,
-            wins: 0.0,
-            sq_wins: 0.0,
+            var: 0.0,
+            q: 0.0,
         }
     }
 
@@ -42,25 +44,19 @@ impl Edge {
     }
 
     pub fn policy(&self) -> f32 {
-        f32::from(self.policy) / f32::from(i16::MAX)
+        f32::from(self.policy) / f32::from(u16::MAX)
     }
 
-    pub fn visits(&self) -> i32 {
+    pub fn visits(&self) -> u32 {
         self.visits
     }
 
-    pub fn wins(&self) -> f32 {
-        self.wins
-    }
-
     pub fn q(&self) -> f32 {
-        self.wins / self.visits as f32
+        self.q as f32
     }
 
     pub fn var(&self) -> f32 {
-        let v = self.visits as f32;
-        let var = self.sq_wins / v - (self.wins / v).powi(2);
-        var.max(0.0)
+        self.var
     }
 
     pub fn set_ptr(&mut self, ptr: i32) {
@@ -68,12 +64,70 @@ impl Edge {
     }
 
     pub fn set_policy(&mut self, policy: f32) {
-        self.policy = (policy * f32::from(i16::MAX)) as i16
+        self.policy = (policy * f32::from(u16::MAX)) as u16
+    }
+
+    pub fn set_stats(&mut self, visits: u32, q: f64, var: f32) {
+        self.visits = visits;
+        self.q = q;
+        self.var = var;
     }
 
     pub fn update(&mut self, result: f32) {
-        self.visits += 1;
-        self.wins += result;
-        self.sq_wins += result.powi(2);
+        // once visits saturate, new results are
+        // weighted as if they had not
+        self.visits = self.visits.saturating_add(1);
+
+        let n = f64::from(self.visits);
+        let result = f64::from(result);
+        let var = f64::from(self.var);
+
+        let delta = result - self.q;
+        self.q += delta / n;
+        self.var = (var + (delta * (result - self.q) - var) / n) as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn q_and_var_are_accurate_after_many_visits() {
+        const VISITS: u32 = 10_000_000;
+
+        assert_eq!(std::mem::size_of::<Edge>(), 24);
+
+        let results = || {
+            let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+
+            (1..=VISITS).map(move |visits| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+
+                // the move is found to be better late in the search
+                let noise = (seed >> 40) as f32 / (1 << 24) as f32 * 0.1;
+                let result = if visits < VISITS / 3 * 2 { 0.3 } else { 0.8 };
+
+                result + noise
+            })
+        };
+
+        let mut edge = Edge::default();
+        results().for_each(|result| edge.update(result));
+
+        // two passes in double precision, for reference
+        let mean = results().map(f64::from).sum::<f64>() / f64::from(VISITS);
+        let var = results()
+            .map(|result| (f64::from(result) - mean).powi(2))
+            .sum::<f64>()
+            / f64::from(VISITS);
+
+        assert_eq!(edge.visits(), VISITS);
+        assert!((f64::from(edge.q()) - mean).abs() < 1e-6);
+
+        // the variance is stored in single precision, so late updates are rounded
+        assert!((f64::from(edge.var()) - var).abs() < 1e-3 * var);
     }
 }
@@ -1,54 +1,78 @@
-#[derive(Clone, Copy, Debug)]
+const ENTRIES_PER_BUCKET: usize = 5;
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
 pub struct HashEntry {
-    pub hash: u64,
-    pub visits: i32,
-    pub wins: f32,
+    key: u16,
+    pub visits: u32,
+    pub q: f32,
 }
 
-impl Default for HashEntry {
-    fn default() -> Self {
-        Self {
-            hash: 0,
-            visits: 0,
-            wins: 0.0,
-        }
-    }
+// a bucket fits in a single cache line
+#[derive(Clone, Copy, Default)]
+#[repr(C, align(64))]
+struct Bucket {
+    entries: [HashEntry; ENTRIES_PER_BUCKET],
 }
 
 pub struct HashTable {
-    table: Vec<HashEntry>,
+    table: Vec<Bucket>,
 }
 
 impl HashTable {
-    pub fn new(size: usize) -> Self {
+    pub fn new_mb(mb: f32) -> Self {
+        let bytes = (mb * 1024.0 * 1024.0) as usize;
+        let size = bytes / std::mem::size_of::<Bucket>();
+
         Self {
-            table: vec![HashEntry::default(); size],
+            table: vec![Bucket::default(); size.max(1)],
         }
     }
 
     pub fn clear(&mut self) {
-        for entry in &mut self.table {
-            *entry = HashEntry::default();
+        for bucket in &mut self.table {
+            *bucket = Bucket::default();
         }
     }
 
-    pub fn fetch(&self, hash: u64) -> &HashEntry {
+    // the low bits of the hash pick the bucket, and the
+    // high bits are kept to verify entries within it
+    fn idx_and_key(&self, hash: u64) -> (usize, u16) {
         let idx = hash % (self.table.len() as u64);
-        &self.table[idx as usize]
+        (idx as usize, (hash >> 48) as u16)
     }
 
     pub fn get(&self, hash: u64) -> Option<HashEntry> {
-        let entry = self.fetch(hash);
+        let (idx, key) = self.idx_and_key(hash);
 
-        if entry.hash == hash {
-            Some(*entry)
-        } else {
-            None
-        }
+        self.table[idx]
+            .entries
+            .iter()
+            .find(|entry| entry.key == key && entry.visits > 0)
+            .copied()
     }
 
-    pub fn push(&mut self, hash: u64, visits: i32, wins: f32) {
-        let idx = hash % (self.table.len() as u64);
-        self.table[idx as usize] = HashEntry { hash, visits, wins };
+    pub fn push(&mut self, hash: u64, visits: u32, q: f32) {
+        let (idx, key) = self.idx_and_key(hash);
+        let entries = &mut self.table[idx].entries;
+
+        // overwrite the entry for this position if present,
+        // otherwise replace the least visited entry
+        let slot = entries
+            .iter()
+            .position(|entry| entry.key == key && entry.visits > 0)
+            .unwrap_or_else(|| {
+                let mut min = 0;
+
+                for (i, entry) in entries.iter().enumerate() {
+                    if entry.visits < entries[min].visits {
+                        min = i;
+                    }
+                }
+
+                min
+            });
+
+        entries[slot] = HashEntry { key, visits, q };
     }
 }
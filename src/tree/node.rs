@@ -1,8 +1,10 @@
-use crate::{tree::Edge, GameRep, GameState, MctsParams};
+use crate::GameState;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct Node {
-    actions: Vec<Edge>,
+    // edges are stored contiguously in the tree's edge arena
+    first_edge: u32,
+    num_edges: u16,
     state: GameState,
     hash: u64,
 
@@ -16,7 +18,8 @@ pub struct Node {
 impl Node {
     pub fn new(state: GameState, hash: u64, parent: i32, action: usize) -> Self {
         Node {
-            actions: Vec::new(),
+            first_edge: 0,
+            num_edges: 0,
             state,
             hash,
             parent,
@@ -34,12 +37,18 @@ impl Node {
         self.state != GameState::Ongoing
     }
 
-    pub fn actions(&self) -> &[Edge] {
-        &self.actions
+    pub fn edges(&self) -> std::ops::Range<usize> {
+        let first = self.first_edge as usize;
+        first..first + self.num_edges()
     }
 
-    pub fn actions_mut(&mut self) -> &mut [Edge] {
-        &mut self.actions
+    pub fn num_edges(&self) -> usize {
+        usize::from(self.num_edges)
+    }
+
+    pub fn set_edges(&mut self, first: usize, num: usize) {
+        self.first_edge = first as u32;
+        self.num_edges = num as u16;
     }
 
     pub fn state(&self) -> GameState {
@@ -63,24 +72,30 @@ impl Node {
     }
 
     pub fn has_children(&self) -> bool {
-        !self.actions.is_empty()
+        self.num_edges > 0
     }
 
     pub fn action(&self) -> usize {
         usize::from(self.action)
     }
 
+    pub fn set_parent(&mut self, parent: i32, action: usize) {
+        self.parent = parent;
+        self.action = action as u16;
+    }
+
     pub fn clear_parent(&mut self) {
         self.parent = -1;
         self.action = 0;
     }
 
     pub fn is_not_expanded(&self) -> bool {
-        self.state == GameState::Ongoing && self.actions.is_empty()
+        self.state == GameState::Ongoing && self.num_edges == 0
     }
 
     pub fn clear(&mut self) {
-        self.actions.clear();
+        self.first_edge = 0;
+        self.num_edges = 0;
         self.state = GameState::Ongoing;
         self.hash = 0;
         self.bwd_link = -1;
@@ -94,72 +109,4 @@ impl Node {
     pub fn set_bwd_link(&mut self, ptr: i32) {
         self.bwd_link = ptr;
     }
-
-    pub fn expand<T: GameRep, const ROOT: bool>(
-        &mut self,
-        pos: &T,
-        params: &MctsParams,
-        policy: &T::Policy,
-    ) {
-        assert!(self.is_not_expanded());
-
-        let feats = pos.get_policy_feats();
-        let mut max = f32::NEG_INFINITY;
-
-        pos.map_legal_moves(|mov| {
-            let policy = pos.get_policy(mov, &feats, policy);
-
-            // trick for calculating policy before quantising
-            self.actions
-                .push(Edge::new(f32::to_bits(policy) as i32, mov.into(), 0));
-            max = max.max(policy);
-        });
-
-        let mut total = 0.0;
-
-        for action in &mut self.actions {
-            let mut policy = f32::from_bits(action.ptr() as u32);
-
-            policy = if ROOT {
-                ((policy - max) / params.root_pst()).exp()
-            } else {
-                (policy - max).exp()
-            };
-
-            action.set_ptr(f32::to_bits(policy) as i32);
-
-            total += policy;
-        }
-
-        for action in &mut self.actions {
-            let policy = f32::from_bits(action.ptr() as u32) / total;
-            action.set_ptr(-1);
-            action.set_policy(policy);
-        }
-    }
-
-    pub fn relabel_policy<T: GameRep>(&mut self, pos: &T, params: &MctsParams, policy: &T::Policy) {
-        let feats = pos.get_policy_feats();
-        let mut max = f32::NEG_INFINITY;
-
-        let mut policies = Vec::new();
-
-        for action in &self.actions {
-            let mov = T::Move::from(action.mov());
-            let policy = pos.get_policy(mov, &feats, policy);
-            policies.push(policy);
-            max = max.max(policy);
-        }
-
-        let mut total = 0.0;
-
-        for policy in &mut policies {
-            *policy = ((*policy - max) / params.root_pst()).exp();
-            total += *policy;
-        }
-
-        for (i, action) in self.actions.iter_mut().enumerate() {
-            action.set_policy(policies[i] / total);
-        }
-    }
 }
@@ -11,8 +11,14 @@ pub struct ValueNetwork<const INPUT: usize, const HIDDEN: usize> {
     l2_bias: i16,
 }
 
-pub trait ValueFeatureMap {
+pub trait ValueFeatureMap: Copy {
     fn value_feature_map<F: FnMut(usize)>(&self, f: F);
+
+    /// Calls `f(feat, true)` for every feature added and `f(feat, false)`
+    /// for every feature removed by the moves that led from `old` to `self`,
+    /// which have the same side to move. Returns `false`, without calling
+    /// `f`, if the features have to be refreshed instead.
+    fn value_feature_deltas<F: FnMut(usize, bool)>(&self, old: &Self, f: F) -> bool;
 }
 
 #[derive(Clone, Copy)]
@@ -21,27 +27,178 @@ struct Accumulator<const HIDDEN: usize> {
     vals: [i16; HIDDEN],
 }
 
+impl<const HIDDEN: usize> Accumulator<HIDDEN> {
+    fn add(&mut self, other: &Self) {
+        for (i, d) in self.vals.iter_mut().zip(&other.vals) {
+            *i += *d;
+        }
+    }
+
+    fn sub(&mut self, other: &Self) {
+        for (i, d) in self.vals.iter_mut().zip(&other.vals) {
+            *i -= *d;
+        }
+    }
+}
+
+/// Hidden layer of a `ValueNetwork`, along with the position it was
+/// produced for, which later positions are updated from.
+#[derive(Clone, Copy)]
+pub struct ValueAccumulator<B, const HIDDEN: usize> {
+    acc: Accumulator<HIDDEN>,
+    board: Option<B>,
+}
+
+impl<B, const HIDDEN: usize> Default for ValueAccumulator<B, HIDDEN> {
+    fn default() -> Self {
+        Self {
+            acc: Accumulator { vals: [0; HIDDEN] },
+            board: None,
+        }
+    }
+}
+
 #[inline]
 fn screlu(x: i16) -> i32 {
     i32::from(x).clamp(0, QA).pow(2)
 }
 
 impl<const INPUT: usize, const HIDDEN: usize> ValueNetwork<INPUT, HIDDEN> {
+    pub const SCALE: i32 = SCALE;
+
     pub fn eval<T: ValueFeatureMap>(&self, board: &T) -> i32 {
-        let mut acc = self.l1_bias;
+        let mut acc = ValueAccumulator::default();
+        self.refresh(board, &mut acc);
+        self.eval_accumulator(&acc)
+    }
+
+    pub fn refresh<T: ValueFeatureMap>(&self, board: &T, acc: &mut ValueAccumulator<T, HIDDEN>) {
+        acc.acc = self.l1_bias;
+        acc.board = Some(*board);
 
-        board.value_feature_map(|feat| {
-            for (i, d) in acc.vals.iter_mut().zip(&self.l1_weights[feat].vals) {
-                *i += *d;
+        board.value_feature_map(|feat| acc.acc.add(&self.l1_weights[feat]));
+    }
+
+    /// Updates `acc` to be the accumulator for `board`, starting from `base`,
+    /// by applying only the features changed by the moves in between.
+    pub fn update<T: ValueFeatureMap>(
+        &self,
+        board: &T,
+        base: &ValueAccumulator<T, HIDDEN>,
+        acc: &mut ValueAccumulator<T, HIDDEN>,
+    ) {
+        let Some(old) = base.board else {
+            self.refresh(board, acc);
+            return;
+        };
+
+        acc.acc = base.acc;
+
+        let updated = board.value_feature_deltas(&old, |feat, add| {
+            if add {
+                acc.acc.add(&self.l1_weights[feat]);
+            } else {
+                acc.acc.sub(&self.l1_weights[feat]);
             }
         });
 
+        if updated {
+            acc.board = Some(*board);
+        } else {
+            self.refresh(board, acc);
+        }
+    }
+
+    pub fn eval_accumulator<T>(&self, acc: &ValueAccumulator<T, HIDDEN>) -> i32 {
         let mut eval = 0;
 
-        for (&v, &w) in acc.vals.iter().zip(self.l2_weights.vals.iter()) {
+        for (&v, &w) in acc.acc.vals.iter().zip(self.l2_weights.vals.iter()) {
             eval += screlu(v) * i32::from(w);
         }
 
         (eval / QA + i32::from(self.l2_bias)) * SCALE / QAB
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comm::UciLike,
+        games::{
+            ataxx::{Ataxx, Uai},
+            chess::{Chess, Uci},
+            shatranj::Shatranj,
+            GameRep, GameState,
+        },
+    };
+
+    fn features<B: ValueFeatureMap>(board: &B) -> Vec<usize> {
+        let mut feats = Vec::new();
+        board.value_feature_map(|feat| feats.push(feat));
+        feats.sort_unstable();
+        feats
+    }
+
+    /// Plays random games, checking that applying the deltas to the features
+    /// of the position two plies earlier gives the features of the current one.
+    fn deltas_match<T: GameRep, B: ValueFeatureMap>(fens: &str, board: impl Fn(&T) -> B) {
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+
+        for fen in fens.lines() {
+            let mut pos = T::from_fen(fen);
+            let mut boards = vec![board(&pos)];
+
+            for _ in 0..64 {
+                let mut moves = Vec::new();
+                pos.map_legal_moves(|mov| moves.push(mov));
+
+                if moves.is_empty() || pos.game_state() != GameState::Ongoing {
+                    break;
+                }
+
+                pos.make_move(moves[rand() % moves.len()]);
+                boards.push(board(&pos));
+
+                if let [.., old, _, new] = boards.as_slice() {
+                    let mut feats = features(old);
+
+                    let updated = new.value_feature_deltas(old, |feat, add| {
+                        if add {
+                            feats.push(feat);
+                        } else {
+                            let idx = feats.iter().position(|&x| x == feat).unwrap();
+                            feats.swap_remove(idx);
+                        }
+                    });
+
+                    if updated {
+                        feats.sort_unstable();
+                        assert_eq!(feats, features(new), "{fen}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chess_deltas() {
+        deltas_match::<Chess, _>(Uci::FEN_STRING, Chess::board);
+    }
+
+    #[test]
+    fn shatranj_deltas() {
+        deltas_match::<Shatranj, _>(Uci::FEN_STRING, Shatranj::board);
+    }
+
+    #[test]
+    fn ataxx_deltas() {
+        deltas_match::<Ataxx, _>(Uai::FEN_STRING, |pos| *pos.board());
+    }
+}
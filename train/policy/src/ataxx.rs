@@ -1,6 +1,6 @@
 use datagen::{PolicyData, Rand};
 use goober::{FeedForwardNetwork, OutputLayer};
-use monty::ataxx::{Ataxx, Move, PolicyNetwork, SubNet};
+use monty::ataxx::{Ataxx, Board, Move, PolicyNetwork, SubNet};
 
 use crate::TrainablePolicy;
 
@@ -12,7 +12,7 @@ impl TrainablePolicy for PolicyNetwork {
             return;
         }
 
-        let board = pos.pos;
+        let board = Board::from(pos.pos);
 
         let feats = board.get_features();
 
@@ -0,0 +1,62 @@
+use goober::SparseVector;
+use monty::{
+    chess::{Board, Castling, PolicyNetwork, QuantisedPolicyNetwork, Uci},
+    UciLike,
+};
+use policy::TrainablePolicy;
+
+use std::{fs::File, io::Read};
+
+/// Reports how far the policy the engine quantises at startup strays from
+/// the unquantised network it is made from. Nothing quantised is written,
+/// as the engine only ever loads the unquantised network.
+fn main() {
+    let mut args = std::env::args();
+    args.next();
+
+    let inp_path = args.next().expect("provide an input path");
+
+    let mut policy = PolicyNetwork::boxed_and_zeroed();
+
+    unsafe {
+        let size_of = std::mem::size_of::<PolicyNetwork>();
+        let ptr: *mut PolicyNetwork = policy.as_mut();
+        let slice = std::slice::from_raw_parts_mut(ptr.cast::<u8>(), size_of);
+        File::open(inp_path)
+            .unwrap()
+            .read_exact(slice)
+            .expect("input is not an unquantised chess policy network");
+    }
+
+    let quantised = QuantisedPolicyNetwork::from_unquantised(&policy);
+
+    validate(&policy, &quantised);
+}
+
+fn validate(policy: &PolicyNetwork, quantised: &QuantisedPolicyNetwork) {
+    let mut max_err = 0f32;
+    let mut total_err = 0.0;
+    let mut count = 0;
+
+    for fen in <Uci as UciLike>::FEN_STRING.lines() {
+        let mut castling = Castling::default();
+        let board = Board::parse_fen(fen, &mut castling);
+
+        let mut feats = SparseVector::with_capacity(32);
+        board.map_policy_features(|feat| feats.push(feat));
+        let threats = board.threats();
+
+        quantised.map_policies(&board, &castling, |mov, quant| {
+            let float = policy.get(&board, &mov, &feats, threats);
+            let err = (float - quant).abs();
+
+            max_err = max_err.max(err);
+            total_err += err;
+            count += 1;
+        });
+    }
+
+    println!("Checked {count} moves against the unquantised network");
+    println!("Mean Error: {:.5}", total_err / count as f32);
+    println!("Max Error: {max_err:.5}");
+}
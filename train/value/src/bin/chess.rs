@@ -109,7 +109,7 @@ impl inputs::InputType for ThreatInputs {
             bb[usize::from(2 + (pc & 7))] ^= bit;
         }
 
-        let board = Board::from_raw(bb, false, 0, 0, 0);
+        let board = Board::from_raw(bb, false, 0, 0, 0, 1);
 
         let threats = board.threats_by(1);
         let defences = board.threats_by(0);
@@ -1,6 +1,6 @@
 use bullet::format::{AtaxxBoard, BulletFormat};
 use datagen::PolicyData;
-use monty::ataxx::Ataxx;
+use monty::ataxx::{Ataxx, Board};
 
 use std::{fs::File, io::BufWriter, time::Instant};
 
@@ -36,7 +36,7 @@ fn main() {
 }
 
 fn into_value(pos: &PolicyData<Ataxx, 114>) -> AtaxxBoard {
-    let board = pos.pos;
+    let board = Board::from(pos.pos);
     let stm = board.stm();
     let bbs = board.bbs();
 